@@ -0,0 +1,284 @@
+//! Measuring the no-reference quality attributes of an image, with a chosen set of metrics
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+
+use crate::{artifacts, blur, count_corners_fast12, count_corners_fast9, laplacian_variance, noise, parse_name, sharpness,
+            MonoImageQAttributes};
+
+/// Pixels darker than this are counted as dark, and those brighter than its complement as bright:
+/// a gaussian distribution centered at 127.5 has exceptional pixels within 1 stddev (255/6) of min and max
+const EXPOSURE_MARGIN: u8 = 43;
+
+/// How long each metric took, in the order they were measured
+pub type MetricTimings = Vec<(&'static str, Duration)>;
+
+/// The no-reference measurements an `Analyzer` can make
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageMetric {
+  /// Laplacian variance, of the u8-clamped filter response
+  Sharpness,
+  /// Variance of the signed Laplacian
+  Laplacian,
+  /// Mean squared Sobel gradient
+  Tenengrad,
+  /// Brenner gradient
+  Brenner,
+  /// Fraction of spectral power at high frequencies
+  Spectral,
+  /// Direction, anisotropy and extent of the blur
+  Blur,
+  /// Standard deviation of the sensor noise
+  Noise,
+  /// Steps at 8x8 and 16x16 block boundaries
+  Blockiness,
+  /// Oscillation beside strong edges
+  Ringing,
+  /// Mean intensity and interquartile spread of the histogram
+  Histogram,
+  /// Entropy of the histogram, recorded as its flatness
+  Entropy,
+  /// Counts and fractions of dark and bright pixels
+  Exposure,
+  /// FAST12 corner count
+  CornersFast12,
+  /// FAST9 corner count
+  CornersFast9,
+}
+
+impl ImageMetric {
+  pub const ALL: [ImageMetric; 14] = [
+    ImageMetric::Sharpness,
+    ImageMetric::Laplacian,
+    ImageMetric::Tenengrad,
+    ImageMetric::Brenner,
+    ImageMetric::Spectral,
+    ImageMetric::Blur,
+    ImageMetric::Noise,
+    ImageMetric::Blockiness,
+    ImageMetric::Ringing,
+    ImageMetric::Histogram,
+    ImageMetric::Entropy,
+    ImageMetric::Exposure,
+    ImageMetric::CornersFast12,
+    ImageMetric::CornersFast9,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      ImageMetric::Sharpness => "sharpness",
+      ImageMetric::Laplacian => "laplacian",
+      ImageMetric::Tenengrad => "tenengrad",
+      ImageMetric::Brenner => "brenner",
+      ImageMetric::Spectral => "spectral",
+      ImageMetric::Blur => "blur",
+      ImageMetric::Noise => "noise",
+      ImageMetric::Blockiness => "blockiness",
+      ImageMetric::Ringing => "ringing",
+      ImageMetric::Histogram => "histogram",
+      ImageMetric::Entropy => "entropy",
+      ImageMetric::Exposure => "exposure",
+      ImageMetric::CornersFast12 => "corners_fast12",
+      ImageMetric::CornersFast9 => "corners_fast9",
+    }
+  }
+
+  /// The `MonoImageQAttributes` fields (and record columns) this metric fills in
+  pub fn fields(&self) -> &'static [&'static str] {
+    match self {
+      ImageMetric::Sharpness => &["sharpness"],
+      ImageMetric::Laplacian => &["laplacian_variance"],
+      ImageMetric::Tenengrad => &["tenengrad"],
+      ImageMetric::Brenner => &["brenner"],
+      ImageMetric::Spectral => &["hf_ratio"],
+      ImageMetric::Blur => &["blur_direction", "blur_anisotropy", "blur_extent"],
+      ImageMetric::Noise => &["noise_sigma"],
+      ImageMetric::Blockiness => &["blockiness_8", "blockiness_16"],
+      ImageMetric::Ringing => &["ringing"],
+      ImageMetric::Histogram => &["mean_intensity", "hist_spread"],
+      ImageMetric::Entropy => &["hist_flatness"],
+      ImageMetric::Exposure => &["dark_pixel_count", "bright_pixel_count", "dark_percent", "bright_percent"],
+      ImageMetric::CornersFast12 => &["corner_count_f12"],
+      ImageMetric::CornersFast9 => &["corner_count_f9"],
+    }
+  }
+
+  fn measure(&self, img: &GrayImage, qattrs: &mut MonoImageQAttributes) {
+    match self {
+      ImageMetric::Sharpness => {
+        let (_laplace_img, sharpness) = laplacian_variance(img);
+        qattrs.sharpness = sharpness;
+      }
+      ImageMetric::Laplacian => qattrs.laplacian_variance = sharpness::laplacian_variance(img),
+      ImageMetric::Tenengrad => qattrs.tenengrad = sharpness::tenengrad(img),
+      ImageMetric::Brenner => qattrs.brenner = sharpness::brenner(img),
+      ImageMetric::Spectral => qattrs.hf_ratio = sharpness::high_frequency_ratio(img),
+      ImageMetric::Blur => {
+        let blur = blur::estimate_blur(img);
+        qattrs.blur_direction = blur.direction;
+        qattrs.blur_anisotropy = blur.anisotropy;
+        qattrs.blur_extent = blur.extent;
+      }
+      ImageMetric::Noise => qattrs.noise_sigma = noise::noise_sigma(img),
+      ImageMetric::Blockiness => {
+        qattrs.blockiness_8 = artifacts::blockiness(img, 8);
+        qattrs.blockiness_16 = artifacts::blockiness(img, 16);
+      }
+      ImageMetric::Ringing => qattrs.ringing = artifacts::ringing(img),
+      ImageMetric::Histogram => histogram_stats(&histogram(img), qattrs),
+      ImageMetric::Entropy => qattrs.hist_flatness = entropy(&histogram(img)),
+      ImageMetric::Exposure => exposure(&histogram(img), qattrs),
+      ImageMetric::CornersFast12 => qattrs.corner_count_f12 = count_corners_fast12(img),
+      ImageMetric::CornersFast9 => qattrs.corner_count_f9 = count_corners_fast9(img),
+    }
+  }
+}
+
+impl fmt::Display for ImageMetric {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for ImageMetric {
+  type Err = String;
+
+  /// Parse a metric name, accepting `-` for `_`
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    parse_name(name, &ImageMetric::ALL, ImageMetric::name, "metric")
+  }
+}
+
+/// Quality attributes of an image, and what it cost to measure them
+#[derive(Debug)]
+pub struct Analysis {
+  pub qattrs: MonoImageQAttributes,
+  pub timings: MetricTimings,
+}
+
+/// Measures a chosen set of no-reference metrics of an image.
+/// Start from `new` (no metrics), `fast` or `full`, and add metrics with `with`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Analyzer {
+  metrics: BTreeSet<ImageMetric>,
+}
+
+impl Analyzer {
+  /// Measures only the image size
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The metrics of `fast_analyze_image`
+  pub fn fast() -> Self {
+    Self::new()
+      .with(ImageMetric::Histogram)
+      .with(ImageMetric::Exposure)
+      .with(ImageMetric::CornersFast12)
+  }
+
+  /// The metrics of `analyze_image`
+  pub fn full() -> Self {
+    Self::new()
+      .with(ImageMetric::Sharpness)
+      .with(ImageMetric::Histogram)
+      .with(ImageMetric::Entropy)
+      .with(ImageMetric::CornersFast12)
+      .with(ImageMetric::CornersFast9)
+  }
+
+  /// Also measure `metric`
+  pub fn with(mut self, metric: ImageMetric) -> Self {
+    self.metrics.insert(metric);
+    self
+  }
+
+  /// Also measure each of `metrics`
+  pub fn with_all<I: IntoIterator<Item = ImageMetric>>(mut self, metrics: I) -> Self {
+    self.metrics.extend(metrics);
+    self
+  }
+
+  /// The enabled metrics, in the order they're measured
+  pub fn metrics(&self) -> impl Iterator<Item = ImageMetric> + '_ {
+    self.metrics.iter().copied()
+  }
+
+  /// Measure each enabled metric, timing each one.
+  /// Attributes of metrics that aren't enabled are left at zero, and aren't in `qattrs.measured`.
+  pub fn analyze(&self, img: &GrayImage) -> Analysis {
+    let mut qattrs = MonoImageQAttributes {
+      width: img.width(),
+      height: img.height(),
+      ..Default::default()
+    };
+    let mut timings = MetricTimings::with_capacity(self.metrics.len());
+    for metric in &self.metrics {
+      let start = Instant::now();
+      metric.measure(img, &mut qattrs);
+      timings.push((metric.name(), start.elapsed()));
+    }
+    qattrs.measured = self.metrics.clone();
+    Analysis { qattrs, timings }
+  }
+}
+
+fn histogram(img: &GrayImage) -> [u32; 256] {
+  imageproc::stats::histogram(img).channels[0]
+}
+
+/// Mean intensity, and the interquartile distance as a fraction of the intensity range
+fn histogram_stats(hist: &[u32; 256], qattrs: &mut MonoImageQAttributes) {
+  let total_pixels: usize = hist.iter().map(|count| *count as usize).sum();
+  let first_quartile_count = total_pixels / 4;
+  let third_quartile_count = 3 * total_pixels / 4;
+  let mut total_intensity: usize = 0;
+  let mut cumulative_count: usize = 0;
+  let mut first_quartile = 0;
+  let mut third_quartile = 0;
+
+  for (intensity, count) in hist.iter().enumerate() {
+    let count = *count as usize;
+    if count == 0 {
+      continue;
+    }
+    total_intensity += count * intensity;
+    cumulative_count += count;
+    if (first_quartile == 0) && (cumulative_count >= first_quartile_count) {
+      first_quartile = intensity;
+    }
+    if (third_quartile == 0) && (cumulative_count >= third_quartile_count) {
+      third_quartile = intensity;
+    }
+  }
+
+  // TODO should we actually use (max_intensity - min_intensity) for divisor (range)?
+  qattrs.hist_spread = (third_quartile - first_quartile) as f64 / 255.0;
+  qattrs.mean_intensity = f32::round(total_intensity as f32 / total_pixels.max(1) as f32) as u8;
+}
+
+/// Shannon entropy of the intensity distribution, in bits
+fn entropy(hist: &[u32; 256]) -> f64 {
+  let total_pixels = hist.iter().map(|count| *count as f64).sum::<f64>();
+  hist.iter()
+    .filter(|count| **count > 0)
+    .map(|count| {
+      let probability = *count as f64 / total_pixels;
+      -probability * probability.log2()
+    })
+    .sum()
+}
+
+fn exposure(hist: &[u32; 256], qattrs: &mut MonoImageQAttributes) {
+  let total_pixels = hist.iter().sum::<u32>().max(1) as f32;
+  qattrs.dark_pixel_count = hist[..EXPOSURE_MARGIN as usize].iter().sum();
+  qattrs.bright_pixel_count = hist[(u8::MAX - EXPOSURE_MARGIN) as usize + 1..].iter().sum();
+  qattrs.dark_percent = qattrs.dark_pixel_count as f32 / total_pixels;
+  qattrs.bright_percent = qattrs.bright_pixel_count as f32 / total_pixels;
+}
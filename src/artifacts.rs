@@ -0,0 +1,140 @@
+//! Compression artifacts: the blocking and ringing of a starved h.264 encoder
+
+use image::GrayImage;
+use imageproc::distance_transform::{distance_transform, Norm};
+use imageproc::gradients::sobel_gradients;
+
+/// Sobel gradient magnitude above which a pixel is on a strong edge:
+/// a slope of 32 gray levels per pixel, as the Sobel response is 8 times the slope
+const EDGE_GRADIENT: u16 = 8 * 32;
+
+/// Ringing is measured on pixels up to this many pixels from a strong edge, but not next to it
+const RING_RADIUS: u8 = 4;
+
+/// Mean absolute difference between neighbouring pixels, for each phase of the block grid.
+/// Each pair is counted at the phase of its right (or lower) pixel, so block boundaries fall in one phase.
+fn phase_differences(img: &GrayImage, block: usize) -> (Vec<f64>, Vec<f64>) {
+  let (width, height) = (img.width() as usize, img.height() as usize);
+  let pixels = img.as_raw();
+  let at = |x: usize, y: usize| pixels[y * width + x] as f64;
+  let mut horizontal = vec![(0.0, 0u64); block];
+  let mut vertical = vec![(0.0, 0u64); block];
+  for y in 0..height {
+    for x in 0..width {
+      if x > 0 {
+        let phase = &mut horizontal[x % block];
+        phase.0 += (at(x, y) - at(x - 1, y)).abs();
+        phase.1 += 1;
+      }
+      if y > 0 {
+        let phase = &mut vertical[y % block];
+        phase.0 += (at(x, y) - at(x, y - 1)).abs();
+        phase.1 += 1;
+      }
+    }
+  }
+  let means = |phases: Vec<(f64, u64)>| -> Vec<f64> {
+    phases.into_iter().map(|(sum, count)| sum / count.max(1) as f64).collect()
+  };
+  (means(horizontal), means(vertical))
+}
+
+/// How much the strongest phase of differences stands out from the others
+fn boundary_ratio(phases: &[f64]) -> f64 {
+  let (boundary, strongest) = phases.iter().enumerate()
+    .fold((0, 0.0), |best, (phase, val)| if *val > best.1 { (phase, *val) } else { best });
+  let others: Vec<f64> = phases.iter().enumerate()
+    .filter(|(phase, _)| *phase != boundary)
+    .map(|(_, val)| *val)
+    .collect();
+  let within = others.iter().sum::<f64>() / others.len().max(1) as f64;
+  if within > 0.0 { strongest / within } else { 1.0 }
+}
+
+/// Blockiness on a grid of `block` pixel squares: how much larger the intensity steps
+/// across block boundaries are than those within blocks, averaged over rows and columns.
+/// About 1 for an unblocked image. Frames are cropped before analysis, so the grid's offset
+/// isn't known: the boundaries are taken to be the grid phase with the largest steps.
+/// Images smaller than two blocks on a side score 1.
+pub fn blockiness(img: &GrayImage, block: usize) -> f64 {
+  if img.width() < 2 * block as u32 || img.height() < 2 * block as u32 {
+    return 1.0;
+  }
+  let (horizontal, vertical) = phase_differences(img, block);
+  (boundary_ratio(&horizontal) + boundary_ratio(&vertical)) / 2.0
+}
+
+/// Ringing: the excess mean absolute Laplacian, in gray levels, of pixels near strong edges
+/// over that of pixels far from them. Oscillations beside edges raise it; so does texture.
+/// Zero if the image has no strong edges, or nothing far from one.
+pub fn ringing(img: &GrayImage) -> f64 {
+  let (width, height) = (img.width() as usize, img.height() as usize);
+  if width < 3 || height < 3 {
+    return 0.0;
+  }
+  let gradients = sobel_gradients(img);
+  let edges = GrayImage::from_fn(img.width(), img.height(), |x, y| {
+    image::Luma([if gradients.get_pixel(x, y).0[0] > EDGE_GRADIENT { 255 } else { 0 }])
+  });
+  let distances = distance_transform(&edges, Norm::LInf);
+
+  let pixels = img.as_raw();
+  let at = |x: usize, y: usize| pixels[y * width + x] as f64;
+  let mut near = (0.0, 0u64);
+  let mut far = (0.0, 0u64);
+  for y in 1..height - 1 {
+    for x in 1..width - 1 {
+      let distance = distances.get_pixel(x as u32, y as u32).0[0];
+      let activity = (4.0 * at(x, y) - at(x - 1, y) - at(x + 1, y) - at(x, y - 1) - at(x, y + 1)).abs();
+      if (2..=RING_RADIUS).contains(&distance) {
+        near.0 += activity;
+        near.1 += 1;
+      } else if distance > 2 * RING_RADIUS {
+        far.0 += activity;
+        far.1 += 1;
+      }
+    }
+  }
+  if near.1 == 0 || far.1 == 0 {
+    return 0.0;
+  }
+  near.0 / near.1 as f64 - far.0 / far.1 as f64
+}
+
+#[cfg(test)]
+mod tests {
+  use imageproc::noise::gaussian_noise;
+
+  use super::*;
+
+  /// A diagonal ramp, optionally flattened within 8x8 blocks, with mild noise
+  fn ramp(blocked: bool) -> GrayImage {
+    let level = |val: u32| if blocked { val / 8 * 8 + 4 } else { val };
+    let img = GrayImage::from_fn(128, 128, |x, y| image::Luma([(level(x) + level(y)) as u8]));
+    gaussian_noise(&img, 0.0, 2.0, 11)
+  }
+
+  /// A vertical step from 40 to 200 at column 64, with `overshoot` decaying oscillations beside it
+  fn step(overshoot: f64) -> GrayImage {
+    GrayImage::from_fn(128, 128, |x, _| {
+      let (base, side, distance) = if x < 64 { (40.0, -1.0, 63 - x) } else { (200.0, 1.0, x - 64) };
+      let ring = overshoot * (-0.6f64).powi(distance as i32);
+      image::Luma([(base + side * ring) as u8])
+    })
+  }
+
+  #[test]
+  fn block_steps_raise_blockiness() {
+    let smooth = blockiness(&ramp(false), 8);
+    let blocked = blockiness(&ramp(true), 8);
+    assert!(smooth < 1.2, "smooth ramp scored {}", smooth);
+    assert!(blocked > 2.0, "blocked ramp scored {}", blocked);
+  }
+
+  #[test]
+  fn overshoot_raises_ringing() {
+    assert_eq!(ringing(&step(0.0)), 0.0);
+    let ringed = ringing(&step(30.0));
+    assert!(ringed > 5.0, "ringing edge scored {}", ringed);
+  }
+}
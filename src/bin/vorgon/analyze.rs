@@ -0,0 +1,100 @@
+//! Per-frame quality attributes of a range of video frames
+
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use image::RgbImage;
+use vorgon::analyzer::Analyzer;
+use vorgon::output::create_output_file;
+use vorgon::preprocessing::Pipeline;
+use vorgon::profile::Profiler;
+use vorgon::quality::QualityModel;
+use vorgon::records::{record_writer, RecordSchema};
+use vorgon::sequence::FrameRecord;
+use vorgon::video::FrameSource;
+
+use crate::cli::{skip_frame_errors, AnalyzerArgs, PipelineArgs, OutputArgs, VideoArgs, VideoName};
+
+
+#[derive(Args, Debug)]
+pub struct AnalyzeArgs {
+  #[command(flatten)]
+  pub video: VideoArgs,
+  #[command(flatten)]
+  pub preprocessing: PipelineArgs,
+  #[command(flatten)]
+  pub analyzer: AnalyzerArgs,
+  #[command(flatten)]
+  pub output: OutputArgs,
+  /// Quality model to classify frames with, as written by `vorgon fit`
+  #[arg(short, long)]
+  pub model: Option<PathBuf>,
+  /// Print what each metric cost per frame
+  #[arg(long)]
+  pub profile: bool,
+}
+
+pub fn run(args: &AnalyzeArgs) -> Result<()> {
+  let model = QualityModel::load_or_default(args.model.as_deref())
+    .with_context(|| format!("can't load quality model {:?}", args.model))?;
+  let name = VideoName::parse(&args.video.input)?;
+  eprintln!("# prefix: {:?} video_id: {:?} start: {} end: {}",
+            name.prefix, name.id, args.video.start, args.video.end_frame());
+  let pipeline = args.preprocessing.load()?;
+
+  // results go to stdout unless an output directory was given,
+  // with the pipeline in a file beside them that `--pipeline` can read back
+  let out: Box<dyn Write + Send> = match &args.output.out_dir {
+    Some(dir) => {
+      let out_path = dir.join(format!("{}-{}-{}.{}", name.prefix, name.id,
+                                      args.video.range_label(), args.output.format.extension()));
+      eprintln!("out_path: {:?}", out_path);
+      std::fs::create_dir_all(dir).with_context(|| format!("can't create output directory {:?}", dir))?;
+      let file = create_output_file(&out_path, args.output.force)
+        .with_context(|| format!("can't create {:?}; --force replaces a previous run", out_path))?;
+      let pipeline_path = dir.join(format!("{}-{}-{}.pipeline.json", name.prefix, name.id, args.video.range_label()));
+      let pipeline_file = create_output_file(&pipeline_path, args.output.force)
+        .with_context(|| format!("can't create {:?}; --force replaces a previous run", pipeline_path))?;
+      pipeline.write(pipeline_file).with_context(|| format!("can't save pipeline {:?}", pipeline_path))?;
+      Box::new(BufWriter::new(file))
+    }
+    None => {
+      eprintln!("# pipeline: {}", serde_json::to_string(&pipeline)?);
+      Box::new(io::stdout())
+    }
+  };
+  let schema = RecordSchema::new(&model);
+  let analyzer = args.analyzer.analyzer(&model);
+  let mut writer = record_writer(args.output.format.record_format(), &schema, out)?;
+
+  let frames = FrameSource::open_indexed(&args.video.input, args.video.start, args.video.end_frame())
+    .with_context(|| format!("can't open video {:?}", args.video.input))?;
+  let mut skipped = Vec::new();
+  let mut profiler = Profiler::new();
+  for frame in frames {
+    let Some((index, pts, rgb_img)) = skip_frame_errors(frame, &mut skipped)
+      .context("can't decode video")? else { continue };
+    let record = analyze_frame(&analyzer, &pipeline, &rgb_img, index, pts);
+    profiler.record(&record.timings);
+    writer.write_row(&schema.row(&record, &model.classify(&record.qattrs)))?;
+  }
+  writer.finish()?;
+  if !skipped.is_empty() {
+    eprintln!("# skipped {} frame errors", skipped.len());
+  }
+  // results may be on stdout
+  if args.profile {
+    eprint!("{}", profiler);
+  }
+
+  Ok(())
+}
+
+/// Analyze a single frame on its own: there's no comparison with the previous frame
+fn analyze_frame(analyzer: &Analyzer, pipeline: &Pipeline, rgb_img: &RgbImage, index: usize, pts: i64) -> FrameRecord {
+  let gray_img = pipeline.apply(rgb_img);
+  let analysis = analyzer.analyze(&gray_img);
+  FrameRecord { index, pts, qattrs: analysis.qattrs, comparison: None, timings: analysis.timings }
+}
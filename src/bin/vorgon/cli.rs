@@ -0,0 +1,217 @@
+//! Options shared by several subcommands
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Args, ValueEnum};
+use regex::Regex;
+use vorgon::analyzer::{Analyzer, ImageMetric};
+use vorgon::metrics::ComparisonMetric;
+use vorgon::preprocessing::{Pipeline, DEFAULT_CROP};
+use vorgon::quality::QualityModel;
+use vorgon::records::RecordFormat;
+use vorgon::video::{DecodedFrame, VideoError};
+use vorgon::vignetting::VignettingModel;
+
+
+/// A video file and the inclusive range of frames to process
+#[derive(Args, Debug)]
+pub struct VideoArgs {
+  /// Video file to read
+  pub input: PathBuf,
+  /// First frame to process
+  #[arg(short, long, default_value_t = 0)]
+  pub start: usize,
+  /// Last frame to process (inclusive); defaults to the end of the video
+  #[arg(short, long)]
+  pub end: Option<usize>,
+}
+
+impl VideoArgs {
+  pub fn end_frame(&self) -> usize {
+    self.end.unwrap_or(usize::MAX)
+  }
+
+  /// Label for the processed range, used in output names
+  pub fn range_label(&self) -> String {
+    match self.end {
+      Some(end) => format!("{}-{}", self.start, end),
+      None => format!("{}-end", self.start),
+    }
+  }
+}
+
+/// Which no-reference metrics to measure each frame by
+#[derive(Args, Debug)]
+pub struct AnalyzerArgs {
+  /// Frame metrics, separated by commas: sharpness, laplacian, tenengrad, brenner, spectral, blur,
+  /// noise, blockiness, ringing, histogram, entropy, exposure, corners_fast12, corners_fast9.
+  /// Whatever the quality model judges is measured too.
+  #[arg(long, value_delimiter = ',', default_values_t = Analyzer::fast().metrics().collect::<Vec<_>>())]
+  pub measure: Vec<ImageMetric>,
+}
+
+impl AnalyzerArgs {
+  /// An analyzer for the chosen metrics, and those `model` needs to classify frames
+  pub fn analyzer(&self, model: &QualityModel) -> Analyzer {
+    Analyzer::new()
+      .with_all(self.measure.iter().copied())
+      .with_all(model.attributes.keys().map(|attr| attr.metric()))
+  }
+}
+
+/// Which full-reference metrics to compare images by
+#[derive(Args, Debug)]
+pub struct MetricArgs {
+  /// Comparison metrics, separated by commas: rmse, psnr, ssim, ms_ssim, gmsd,
+  /// hist_correlation, hellinger, chi_square, bhattacharyya
+  #[arg(long, value_delimiter = ',', default_values_t = ComparisonMetric::DEFAULT_SET)]
+  pub metrics: Vec<ComparisonMetric>,
+}
+
+/// Pass on a decoded frame, or log a frame error to `skipped` and return None.
+/// Errors that end decoding are returned.
+pub fn skip_frame_errors(frame: Result<DecodedFrame, VideoError>, skipped: &mut Vec<String>)
+  -> Result<Option<DecodedFrame>, VideoError>
+{
+  match frame {
+    Ok(frame) => Ok(Some(frame)),
+    Err(e) if e.is_frame_error() => {
+      eprintln!("Skipping: {}", e);
+      skipped.push(e.to_string());
+      Ok(None)
+    }
+    Err(e) => Err(e),
+  }
+}
+
+/// How frames are preprocessed before analysis
+#[derive(Args, Debug)]
+pub struct PipelineArgs {
+  /// Preprocessing pipeline config: TOML if named `*.toml`, otherwise JSON.
+  /// Without one, frames are mixed to red-green luma, devignetted with any --vignetting model, then cropped.
+  #[arg(long, conflicts_with_all = ["crop", "vignetting"])]
+  pub pipeline: Option<PathBuf>,
+  /// Fraction of each dimension to keep, centered;
+  /// defaults to 0.8, or to the full frame with --vignetting
+  #[arg(long, value_parser = parse_crop)]
+  pub crop: Option<f32>,
+  /// Vignetting model to correct frames with before cropping, as written by `vorgon vignetting`
+  #[arg(long)]
+  pub vignetting: Option<PathBuf>,
+}
+
+impl PipelineArgs {
+  pub fn load(&self) -> Result<Pipeline> {
+    if let Some(path) = &self.pipeline {
+      return Pipeline::load(path).with_context(|| format!("can't load pipeline {:?}", path));
+    }
+    let vignetting = self.vignetting.as_deref()
+      .map(|path| VignettingModel::load(path).with_context(|| format!("can't load vignetting model {:?}", path)))
+      .transpose()?;
+    let uncropped = if vignetting.is_some() { 1.0 } else { DEFAULT_CROP };
+    Ok(Pipeline::cropped(self.crop.unwrap_or(uncropped), vignetting))
+  }
+}
+
+fn parse_crop(arg: &str) -> Result<f32, String> {
+  let crop: f32 = arg.parse().map_err(|e| format!("{}", e))?;
+  if crop > 0.0 && crop <= 1.0 {
+    Ok(crop)
+  } else {
+    Err(format!("crop must be in (0, 1], got {}", crop))
+  }
+}
+
+/// Where and how to write results
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+  /// Root directory to write outputs under
+  #[arg(short, long)]
+  pub out_dir: Option<PathBuf>,
+  /// Format of per-frame results
+  #[arg(short, long, value_enum, default_value_t = OutputFormat::Csv)]
+  pub format: OutputFormat,
+  /// Replace the outputs of a previous run instead of stopping
+  #[arg(long)]
+  pub force: bool,
+}
+
+impl OutputArgs {
+  /// The output directory, or `default` if none was given
+  pub fn dir_or<'a>(&'a self, default: &'a Path) -> &'a Path {
+    self.out_dir.as_deref().unwrap_or(default)
+  }
+}
+
+/// Formats for per-frame results
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+  Csv,
+  /// JSON Lines: one object per frame
+  Jsonl,
+  /// One JSON object of column arrays, for loading directly into a data frame
+  Columnar,
+  /// Apache Parquet; `segments` writes every segment into one file
+  #[cfg(feature = "parquet")]
+  Parquet,
+}
+
+impl OutputFormat {
+  pub fn record_format(&self) -> RecordFormat {
+    match self {
+      OutputFormat::Csv => RecordFormat::Csv,
+      OutputFormat::Jsonl => RecordFormat::JsonLines,
+      OutputFormat::Columnar => RecordFormat::Columnar,
+      #[cfg(feature = "parquet")]
+      OutputFormat::Parquet => RecordFormat::Parquet,
+    }
+  }
+
+  pub fn extension(&self) -> &'static str {
+    self.record_format().extension()
+  }
+
+  /// Whether `segments` writes one table for the whole run, rather than one per segment
+  pub fn combines_segments(&self) -> bool {
+    #[cfg(feature = "parquet")]
+    if *self == OutputFormat::Parquet {
+      return true;
+    }
+    false
+  }
+}
+
+/// Formats for saved frame images
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+  Jpg,
+  Png,
+}
+
+impl ImageFormat {
+  pub fn extension(&self) -> &'static str {
+    match self {
+      ImageFormat::Jpg => "jpg",
+      ImageFormat::Png => "png",
+    }
+  }
+}
+
+/// The camera prefix and numeric id of a video named like `prefix-1670019436.mp4`
+pub struct VideoName {
+  pub prefix: String,
+  pub id: String,
+}
+
+impl VideoName {
+  pub fn parse(path: &Path) -> Result<Self> {
+    let regx = Regex::new(r"(\w+)\-(\d+)\.").unwrap();
+    let file_name = path.file_name()
+      .and_then(|name| name.to_str())
+      .ok_or_else(|| anyhow!("not a video file name: {:?}", path))?;
+    let finds = regx.captures(file_name)
+      .ok_or_else(|| anyhow!("expected a video named like prefix-1234.mp4, got {:?}", file_name))?;
+    Ok(Self { prefix: finds[1].to_string(), id: finds[2].to_string() })
+  }
+}
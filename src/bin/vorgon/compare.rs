@@ -0,0 +1,137 @@
+//! Compare the annotated frames saved by the slow, fast and delta extractors
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use image::GrayImage;
+use vorgon::compare_images_with;
+use vorgon::manifest::Manifest;
+use vorgon::metrics::ComparisonMetric;
+
+use crate::cli::{MetricArgs, VideoName};
+
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+  /// Approaches manifest (JSON)
+  pub manifest: PathBuf,
+  /// Directory holding the `<id>_{slow,fast,delta}_<frame>.png` annotated frames;
+  /// defaults to `annotated_frames` beside the manifest
+  #[arg(long)]
+  pub frames_dir: Option<PathBuf>,
+  // SSIM and histogram correlation decide which frame is most similar, so are always compared
+  #[command(flatten)]
+  pub metrics: MetricArgs,
+}
+
+fn compare_two(video_id: &str, frame_id: u32, one: &Path, two: &Path, metrics: &[ComparisonMetric])
+  -> Option<(f64, f64)>
+{
+  // the image frame collected by the slow process is more accurate?
+  let first_image = match image::open(one) {
+    Ok(img) => img,
+    Err(_) => {
+      println!("\r\n<<< missing first file path: {:?}", one);
+      return None;
+    }
+  };
+  // the image collected by the fast process is usually within 4 frames of the slow process
+  let second_image = match image::open(two) {
+    Ok(img) => img,
+    Err(_) => {
+      println!("\r\n<<< missing second file path: {:?}", two);
+      return None;
+    }
+  };
+  let first_gray: GrayImage = first_image.into_luma8();
+  let second_gray: GrayImage = second_image.into_luma8();
+  let (cmp, _) = match compare_images_with(&first_gray, &second_gray, metrics, false) {
+    Ok(compared) => compared,
+    Err(e) => {
+      println!("\r\n<<< can't compare {:?} with {:?}: {}", one, two, e);
+      return None;
+    }
+  };
+  let hsim = cmp.hsim_score.unwrap_or_default();
+  let ssim = cmp.ssim_score.unwrap_or_default();
+  println!("\r\n=== video_id: {} frame_id: {} === hsim {:+e}, ssim {:+e} ",
+           video_id, frame_id, hsim, ssim);
+  let others: Vec<String> = metrics.iter()
+    .filter(|metric| !matches!(metric, ComparisonMetric::Ssim | ComparisonMetric::HistCorrelation))
+    .filter_map(|metric| cmp.get(*metric).map(|value| format!("{} {:+e}", metric, value)))
+    .collect();
+  if !others.is_empty() {
+    println!("    {}", others.join(", "));
+  }
+  Some((hsim, ssim))
+}
+
+pub fn run(args: &CompareArgs) -> Result<()> {
+  let manifest_path = args.manifest.as_path();
+  println!("manifest_path: {:?}", manifest_path);
+
+  let manifest = Manifest::load(manifest_path)
+    .with_context(|| format!("can't load manifest {:?}", manifest_path))?;
+  let report = manifest.validate(|_| None);
+  if !report.is_clean() {
+    eprint!("{}", report);
+  }
+
+  let target_dir = match &args.frames_dir {
+    Some(dir) => dir.clone(),
+    None => manifest_path.parent().unwrap_or(Path::new(".")).join("annotated_frames"),
+  };
+
+  let mut metrics = args.metrics.metrics.clone();
+  for metric in ComparisonMetric::DEFAULT_SET {
+    if !metrics.contains(&metric) {
+      metrics.push(metric);
+    }
+  }
+
+  let segments = manifest.segments();
+  println!("nsegments: {}", segments.len());
+
+  for seg in segments.iter().filter(|seg| seg.validated_runway) {
+    let video_id = match VideoName::parse(&seg.file_path) {
+      Ok(name) => name.id,
+      Err(e) => {
+        eprintln!("{:#}", e);
+        continue;
+      }
+    };
+    let frame_path = |source: &str| {
+      target_dir.join(format!("{}_{}_{}.png", video_id, source, seg.annotated_frame))
+    };
+    let slow_file_path = frame_path("slow");
+    let fast_file_path = frame_path("fast");
+    let delta_file_path = frame_path("delta");
+
+    if let Some((hsim0, ssim0)) = compare_two(
+      &video_id, seg.annotated_frame, &slow_file_path, &slow_file_path, &metrics) {
+      if ssim0 < 1.0 || hsim0 < 1.0 {
+        println!("<<< baseline SSIM: {} HSIM: {}", ssim0, hsim0);
+      }
+    }
+
+    let Some((hsim1, ssim1)) = compare_two(
+      &video_id, seg.annotated_frame, &slow_file_path, &fast_file_path, &metrics) else { continue };
+    let Some((hsim2, ssim2)) = compare_two(
+      &video_id, seg.annotated_frame, &slow_file_path, &delta_file_path, &metrics) else { continue };
+    if ssim1 > ssim2 {
+      println!("<<< Fast most similar SSIM by {:0.9}. HSIM {:0.8} vs {:0.8}",
+               ssim1 - ssim2, hsim1, hsim2);
+    } else if ssim2 > ssim1 {
+      println!("<<< Delta most similar SSIM by {:0.9}. HSIM {:0.8} vs {:0.8}",
+               ssim2 - ssim1, hsim2, hsim1);
+    } else if hsim1 == hsim2 {
+      println!("<<< Delta and Fast fully identical? SSIM {:+e}  HSIM {:+e} ", ssim1, hsim1);
+    } else {
+      // in practice we don't ever seem to hit this
+      println!("<<< Delta and Fast SSIM identical: {:+e}. HSIM {:+e} vs {:+e}", ssim1, hsim2, hsim1);
+    }
+  }
+
+  Ok(())
+}
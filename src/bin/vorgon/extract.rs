@@ -0,0 +1,61 @@
+//! Save a range of video frames as cropped RGB images
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use vorgon::output::{FrameOutput, OutputLayout, RunManifest};
+use vorgon::video::FrameSource;
+
+use crate::cli::{skip_frame_errors, PipelineArgs, ImageFormat, OutputArgs, VideoArgs, VideoName};
+
+
+#[derive(Args, Debug)]
+pub struct ExtractArgs {
+  #[command(flatten)]
+  pub video: VideoArgs,
+  #[command(flatten)]
+  pub preprocessing: PipelineArgs,
+  // frames go under ./frames unless --out-dir is given
+  #[command(flatten)]
+  pub output: OutputArgs,
+  /// Format of the saved frames
+  #[arg(long, value_enum, default_value_t = ImageFormat::Jpg)]
+  pub image_format: ImageFormat,
+}
+
+pub fn run(args: &ExtractArgs) -> Result<()> {
+  let name = VideoName::parse(&args.video.input)?;
+  println!("prefix: {:?} video_id: {:?}", name.prefix, name.id);
+
+  let layout = OutputLayout::new(args.output.dir_or(Path::new("frames")));
+  let segment = args.video.range_label();
+  let segment_dir = layout.create_segment_dir(&name.id, &segment, args.output.force)
+    .with_context(|| format!("can't create output directory for {} {}; --force replaces a previous run",
+                             name.id, segment))?;
+  println!("output frames to: {:?}", segment_dir);
+
+  let pipeline = args.preprocessing.load()?;
+  let mut run_manifest = RunManifest::new("extract", &args.video.input, &name.id, &segment,
+                                          args.video.start, args.video.end)
+    .setting("prefix", &name.prefix)
+    .setting("image_format", args.image_format.extension())
+    .setting("pipeline", &pipeline);
+
+  let frames = FrameSource::open_indexed(&args.video.input, args.video.start, args.video.end_frame())
+    .with_context(|| format!("can't open video {:?}", args.video.input))?;
+  for frame in frames {
+    let Some((index, pts, rgb_img)) = skip_frame_errors(frame, &mut run_manifest.skipped)
+      .context("can't decode video")? else { continue };
+    // color frames go through the stages after channel mixing, each channel alike
+    let crop_img = pipeline.apply_rgb(&rgb_img);
+
+    let file_name = format!("frame_{:06}.{}", index, args.image_format.extension());
+    let full_path = segment_dir.join(&file_name);
+    crop_img.save(&full_path).with_context(|| format!("can't save frame {:?}", full_path))?;
+    println!("le_filename: {}", file_name);
+    run_manifest.frames.push(FrameOutput { index, pts, files: vec![file_name] });
+  }
+
+  run_manifest.finish(&segment_dir).context("can't write run manifest")
+}
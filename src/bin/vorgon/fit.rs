@@ -0,0 +1,41 @@
+//! Fit a quality model from a set of known-good (preprocessed, grayscale) frames
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use vorgon::fast_analyze_image;
+use vorgon::quality::{FitMethod, QualityAttribute, QualityModel};
+
+
+#[derive(Args, Debug)]
+pub struct FitArgs {
+  /// Where to save the fitted model (JSON)
+  pub model: PathBuf,
+  /// Known-good frame images
+  #[arg(required = true)]
+  pub images: Vec<PathBuf>,
+  /// Use median/MAD statistics instead of mean/stddev
+  #[arg(long)]
+  pub robust: bool,
+}
+
+pub fn run(args: &FitArgs) -> Result<()> {
+  let method = if args.robust { FitMethod::MedianMad } else { FitMethod::MeanStdDev };
+
+  let mut samples = Vec::new();
+  for image_path in &args.images {
+    match image::open(image_path) {
+      Ok(img) => samples.push(fast_analyze_image(&img.into_luma8())),
+      Err(e) => eprintln!("Unable to open {:?}: {}", image_path, e),
+    }
+  }
+  println!("nsamples: {}", samples.len());
+
+  let model = QualityModel::fit(&samples, &QualityAttribute::DEFAULT_SET, method)
+    .context("need at least one readable known-good frame")?;
+  for (attr, stats) in &model.attributes {
+    println!("{}: center {:0.4} spread {:0.4}", attr.name(), stats.center, stats.spread);
+  }
+  model.save(&args.model).with_context(|| format!("can't save model {:?}", args.model))
+}
@@ -0,0 +1,55 @@
+//! Build frame index sidecars for every video referenced by an approaches manifest
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use vorgon::manifest::Manifest;
+use vorgon::video::{FrameIndex, sidecar_path};
+
+
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+  /// Approaches manifest (JSON)
+  pub manifest: PathBuf,
+  /// Discard any existing sidecars and rescan every video
+  #[arg(long)]
+  pub rebuild: bool,
+}
+
+/// Collect the distinct video files referenced by the manifest
+fn get_video_list(manifest: &Manifest) -> BTreeSet<PathBuf> {
+  manifest.segments().into_iter()
+    .map(|seg| seg.file_path)
+    .collect()
+}
+
+pub fn run(args: &IndexArgs) -> Result<()> {
+  println!("manifest_path: {:?}", args.manifest);
+
+  let manifest = Manifest::load(&args.manifest)
+    .with_context(|| format!("can't load manifest {:?}", args.manifest))?;
+  let videos = get_video_list(&manifest);
+  println!("nvideos: {}", videos.len());
+
+  for video_path in videos {
+    if !args.rebuild && FrameIndex::load(&video_path).is_ok() {
+      println!("current: {:?}", sidecar_path(&video_path));
+      continue;
+    }
+    match FrameIndex::scan(&video_path) {
+      Ok(index) => {
+        let nkeys = index.frames.iter().filter(|entry| entry.keyframe).count();
+        match index.save(&video_path) {
+          Ok(()) => println!("indexed: {:?} frames: {} keyframes: {}",
+                             sidecar_path(&video_path), index.frame_count(), nkeys),
+          Err(e) => eprintln!("Unable to save index for {:?}: {}", video_path, e),
+        }
+      }
+      Err(e) => eprintln!("Unable to index {:?}: {}", video_path, e),
+    }
+  }
+
+  Ok(())
+}
@@ -0,0 +1,56 @@
+//! Command line interface to the vorgon video quality tools
+
+mod analyze;
+mod cli;
+mod compare;
+mod extract;
+mod fit;
+mod index;
+mod preprocess;
+mod segments;
+mod vignetting;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+
+#[derive(Parser, Debug)]
+#[command(name = "vorgon", version, about = "Image quality analysis of runway approach and takeoff video")]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Save a range of video frames as cropped RGB images
+  Extract(extract::ExtractArgs),
+  /// Print quality attributes and classification of a range of video frames
+  Analyze(analyze::AnalyzeArgs),
+  /// Analyze every runway segment of a manifest, one records file per segment
+  Segments(segments::SegmentsArgs),
+  /// Compare the slow, fast and delta annotated frames of a manifest
+  Compare(compare::CompareArgs),
+  /// Save preprocessed frames of a video, with their quality attributes
+  Preprocess(preprocess::PreprocessArgs),
+  /// Build frame index sidecars for the videos of a manifest
+  Index(index::IndexArgs),
+  /// Fit a quality model from known-good frame images
+  Fit(fit::FitArgs),
+  /// Fit a vignetting model from uncropped frame images
+  Vignetting(vignetting::VignettingArgs),
+}
+
+fn main() -> Result<()> {
+  let cli = Cli::parse();
+  match &cli.command {
+    Command::Extract(args) => extract::run(args),
+    Command::Analyze(args) => analyze::run(args),
+    Command::Segments(args) => segments::run(args),
+    Command::Compare(args) => compare::run(args),
+    Command::Preprocess(args) => preprocess::run(args),
+    Command::Index(args) => index::run(args),
+    Command::Fit(args) => fit::run(args),
+    Command::Vignetting(args) => vignetting::run(args),
+  }
+}
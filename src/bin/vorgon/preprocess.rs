@@ -0,0 +1,114 @@
+//! Save preprocessed (grayscale) and raw frames, with their quality attributes
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use image::RgbImage;
+use vorgon::analyzer::Analyzer;
+use vorgon::output::{FrameOutput, OutputLayout, RunManifest};
+use vorgon::preprocessing::Pipeline;
+use vorgon::profile::Profiler;
+use vorgon::quality::QualityModel;
+use vorgon::records::{record_writer, RecordSchema};
+use vorgon::sequence::FrameRecord;
+use vorgon::video::FrameSource;
+
+use crate::cli::{skip_frame_errors, AnalyzerArgs, PipelineArgs, ImageFormat, OutputArgs, VideoArgs, VideoName};
+
+
+#[derive(Args, Debug)]
+pub struct PreprocessArgs {
+  #[command(flatten)]
+  pub video: VideoArgs,
+  #[command(flatten)]
+  pub preprocessing: PipelineArgs,
+  #[command(flatten)]
+  pub analyzer: AnalyzerArgs,
+  // frames go under ./preproc unless --out-dir is given
+  #[command(flatten)]
+  pub output: OutputArgs,
+  /// Format of the saved frames
+  #[arg(long, value_enum, default_value_t = ImageFormat::Jpg)]
+  pub image_format: ImageFormat,
+  /// Quality model to classify frames with, as written by `vorgon fit`
+  #[arg(short, long)]
+  pub model: Option<PathBuf>,
+  /// Print what each metric cost per frame
+  #[arg(long)]
+  pub profile: bool,
+}
+
+pub fn run(args: &PreprocessArgs) -> Result<()> {
+  let model = QualityModel::load_or_default(args.model.as_deref())
+    .with_context(|| format!("can't load quality model {:?}", args.model))?;
+  let analyzer = args.analyzer.analyzer(&model);
+  let pipeline = args.preprocessing.load()?;
+  let name = VideoName::parse(&args.video.input)?;
+
+  let layout = OutputLayout::new(args.output.dir_or(Path::new("preproc")));
+  let segment = args.video.range_label();
+  let segment_dir = layout.create_segment_dir(&name.id, &segment, args.output.force)
+    .with_context(|| format!("can't create output directory for {} {}; --force replaces a previous run",
+                             name.id, segment))?;
+  println!("# output frames to: {:?}", segment_dir);
+
+  let mut run_manifest = RunManifest::new("preprocess", &args.video.input, &name.id, &segment,
+                                          args.video.start, args.video.end)
+    .setting("prefix", &name.prefix)
+    .setting("pipeline", &pipeline)
+    .setting("image_format", args.image_format.extension())
+    .setting("model", &args.model)
+    .setting("measure", analyzer.metrics().collect::<Vec<_>>());
+
+  println!("# prefix: {:?} video_id: {:?} start: {} end: {}",
+           name.prefix, name.id, args.video.start, args.video.end_frame());
+
+  let records_name = format!("records.{}", args.output.format.extension());
+  let records_file = File::create(segment_dir.join(&records_name))
+    .with_context(|| format!("can't create {:?}", segment_dir.join(&records_name)))?;
+  let schema = RecordSchema::new(&model);
+  let mut writer = record_writer(args.output.format.record_format(), &schema, BufWriter::new(records_file))?;
+  run_manifest.records = Some(records_name);
+
+  let frames = FrameSource::open_indexed(&args.video.input, args.video.start, args.video.end_frame())
+    .with_context(|| format!("can't open video {:?}", args.video.input))?;
+  let mut profiler = Profiler::new();
+  for frame in frames {
+    let Some((index, pts, rgb_img)) = skip_frame_errors(frame, &mut run_manifest.skipped)
+      .context("can't decode video")? else { continue };
+    let (files, record) = process_frame(&analyzer, &pipeline, &rgb_img, index, pts, args, &segment_dir)?;
+    profiler.record(&record.timings);
+    writer.write_row(&schema.row(&record, &model.classify(&record.qattrs)))?;
+    run_manifest.frames.push(FrameOutput { index, pts, files });
+  }
+  writer.finish().context("can't write frame records")?;
+  if args.profile {
+    print!("{}", profiler);
+  }
+
+  run_manifest.finish(&segment_dir).context("can't write run manifest")
+}
+
+/// Save the frame's images, returning the names of the saved files and the frame's analysis
+fn process_frame(analyzer: &Analyzer, pipeline: &Pipeline, rgb_img: &RgbImage,
+                 index: usize, pts: i64, args: &PreprocessArgs, segment_dir: &Path)
+  -> Result<(Vec<String>, FrameRecord)>
+{
+  let gray_img = pipeline.apply(rgb_img);
+  let extension = args.image_format.extension();
+
+  let gray_file_name = format!("frame_{:06}_gray.{}", index, extension);
+  let full_path = segment_dir.join(&gray_file_name);
+  gray_img.save(&full_path).with_context(|| format!("can't save frame {:?}", full_path))?;
+
+  let rgb_file_name = format!("frame_{:06}_rgb.{}", index, extension);
+  let full_path = segment_dir.join(&rgb_file_name);
+  rgb_img.save(&full_path).with_context(|| format!("can't save frame {:?}", full_path))?;
+
+  let analysis = analyzer.analyze(&gray_img);
+  let record = FrameRecord { index, pts, qattrs: analysis.qattrs, comparison: None, timings: analysis.timings };
+  Ok((vec![gray_file_name, rgb_file_name], record))
+}
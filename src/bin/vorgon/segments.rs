@@ -0,0 +1,539 @@
+//! Analyze every runway segment of a manifest, writing a records file per segment
+//! (or, for Parquet, one file for the whole run)
+
+use std::collections::BTreeMap;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+#[cfg(feature = "sqlite")]
+use serde_json::Value;
+use vorgon::analyzer::{Analyzer, MetricTimings};
+use vorgon::checkpoint::{hash_file, segment_key, Checkpoint, SegmentOutcome};
+use vorgon::pipeline::ordered_pipeline;
+use vorgon::preprocessing::Pipeline;
+use vorgon::profile::Profiler;
+use vorgon::manifest::{Manifest, SegmentDescriptor, SegmentKind, ValidationReport};
+use vorgon::output::create_output_file;
+use vorgon::quality::QualityModel;
+#[cfg(feature = "sqlite")]
+use vorgon::quality::QualityAttribute;
+#[cfg(feature = "sqlite")]
+use vorgon::records::set_classification;
+use vorgon::records::{record_writer, RecordSchema, RecordWriter, Row, SegmentContext};
+use vorgon::sequence::SequenceAnalyzer;
+#[cfg(feature = "sqlite")]
+use vorgon::store::{Provenance, ResultStore};
+use vorgon::video::FrameSource;
+
+use crate::cli::{skip_frame_errors, AnalyzerArgs, PipelineArgs, MetricArgs, OutputArgs, VideoName};
+
+
+#[derive(Args, Debug)]
+pub struct SegmentsArgs {
+  /// Approaches manifest (JSON)
+  pub manifest: PathBuf,
+  #[command(flatten)]
+  pub preprocessing: PipelineArgs,
+  #[command(flatten)]
+  pub analyzer: AnalyzerArgs,
+  #[command(flatten)]
+  pub metrics: MetricArgs,
+  // per-segment results go alongside the manifest unless --out-dir is given
+  #[command(flatten)]
+  pub output: OutputArgs,
+  /// Quality model to classify frames with, as written by `vorgon fit`
+  #[arg(short, long)]
+  pub model: Option<PathBuf>,
+  /// Number of segments to process concurrently; defaults to the number of cores
+  #[arg(short, long)]
+  pub jobs: Option<usize>,
+  /// Analysis threads per segment; defaults to sharing the cores between jobs
+  #[arg(short, long)]
+  pub workers: Option<usize>,
+  /// Also store results in this SQLite database. Segments it already holds, made with the same
+  /// pipeline and metrics, are written out from it rather than analyzed again (unless --force)
+  #[cfg(feature = "sqlite")]
+  #[arg(long)]
+  pub db: Option<PathBuf>,
+  /// Skip segments the checkpoint records as completed, and retry the rest, partial ones included
+  #[arg(long)]
+  pub resume: bool,
+  /// Checkpoint file; defaults to `<manifest name>.checkpoint.json` in the output directory
+  #[arg(long)]
+  pub checkpoint: Option<PathBuf>,
+  /// Print what each metric cost per frame, over the whole run
+  #[arg(long)]
+  pub profile: bool,
+}
+
+/// Where the rows of each segment go
+enum RunOutput {
+  /// A records file per segment
+  PerSegment,
+  /// One table for the whole run, shared by the segment jobs
+  Combined(Mutex<CombinedOutput>),
+}
+
+/// The run's single table. Segments complete in any order, but their rows are written
+/// in the order of the manifest, so the table is the same however many jobs run.
+struct CombinedOutput {
+  writer: Box<dyn RecordWriter>,
+  /// Rows of completed segments waiting for those before them, by position in the run
+  pending: BTreeMap<usize, Vec<Row>>,
+  /// Position of the next segment to write
+  next: usize,
+  /// The first write error, reported when the table is finished
+  result: io::Result<()>,
+}
+
+impl CombinedOutput {
+  fn new(writer: Box<dyn RecordWriter>) -> Self {
+    Self { writer, pending: BTreeMap::new(), next: 0, result: Ok(()) }
+  }
+
+  /// Add the rows of the segment at `position`, writing them once those before it are written.
+  /// Every position must be added, with no rows for a failed segment.
+  fn add_segment(&mut self, position: usize, rows: Vec<Row>) {
+    self.pending.insert(position, rows);
+    while let Some(rows) = self.pending.remove(&self.next) {
+      self.next += 1;
+      for row in &rows {
+        if self.result.is_ok() {
+          self.result = self.writer.write_row(row);
+        }
+      }
+    }
+  }
+
+  fn finish(mut self) -> io::Result<()> {
+    self.result?;
+    self.writer.finish()
+  }
+}
+
+/// State shared by the segment jobs of one run
+struct Run<'a> {
+  args: &'a SegmentsArgs,
+  manifest: &'a Manifest,
+  /// Problems found in the manifest before the run, without the videos' frame sizes
+  report: ValidationReport,
+  model: QualityModel,
+  analyzer: Analyzer,
+  pipeline: Pipeline,
+  schema: RecordSchema,
+  out_dir: &'a Path,
+  workers: usize,
+  output: RunOutput,
+  #[cfg(feature = "sqlite")]
+  store: Option<Mutex<ResultStore>>,
+  /// How this run makes rows, so stored rows made differently are made again
+  #[cfg(feature = "sqlite")]
+  provenance: Provenance,
+  checkpoint: Mutex<Checkpoint>,
+  checkpoint_path: PathBuf,
+  summary: Mutex<RunSummary>,
+  profiler: Mutex<Profiler>,
+}
+
+/// Tally of segment outcomes, reported when the run ends
+#[derive(Debug, Default)]
+struct RunSummary {
+  ok: usize,
+  /// Completed by an earlier run
+  already_completed: usize,
+  /// Segment key and description of the skipped frames
+  partial: Vec<(String, String)>,
+  /// Segment key and reason
+  failed: Vec<(String, String)>,
+}
+
+/// What processing a segment wrote, and the frame errors it skipped over
+struct Processed {
+  /// The segment's own output file, if it has one
+  output: Option<PathBuf>,
+  /// The segment's rows, if they're kept for the combined table or the results database
+  rows: Vec<Row>,
+  skipped: Vec<String>,
+}
+
+impl Run<'_> {
+  /// Decode and analyze a segment, handing each frame's row to `write_row` in frame order.
+  /// Frames that can't be decoded or analyzed are skipped; returns a description of each.
+  fn analyze_segment(&self, segment: &SegmentDescriptor, context: &SegmentContext,
+                     write_row: &mut dyn FnMut(&Row) -> io::Result<()>) -> Result<Vec<String>> {
+    println!("frame start {} end {}", segment.start_frame, segment.end_frame);
+
+    let mut source_error = None;
+    let mut decode_skipped = Vec::new();
+    let mut analysis_skipped = Vec::new();
+    let mut write_result = Ok(());
+    let mut profiler = Profiler::new();
+    // decode on one thread, analyze on the workers, write in frame order here
+    ordered_pipeline(
+      2 * self.workers,
+      self.workers,
+      |send| {
+        let frames = match FrameSource::open_indexed(&segment.file_path,
+                                                     segment.start_frame as usize,
+                                                     segment.end_frame as usize) {
+          Ok(frames) => frames,
+          Err(e) => {
+            source_error = Some(e);
+            return;
+          }
+        };
+        // the frame size comes with the video's index, so annotations are checked against it here
+        for problem in self.manifest.frame_problems(segment, frames.width(), frames.height()) {
+          eprintln!("Manifest problem: {}", problem);
+        }
+        // each segment starts with no prior frame to compare against
+        let mut sequence = SequenceAnalyzer::new(self.analyzer.clone(), &self.args.metrics.metrics);
+        for frame in frames {
+          let (index, pts, rgb_img) = match skip_frame_errors(frame, &mut decode_skipped) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => continue,
+            Err(e) => {
+              source_error = Some(e);
+              break;
+            }
+          };
+          // for image quality analysis we're mostly interested in grayscale
+          let gray_img = self.pipeline.apply(&rgb_img);
+          if !send(sequence.pair(index, pts, gray_img)) {
+            break;
+          }
+        }
+      },
+      |pair| {
+        let record = pair.analyze()
+          .map_err(|e| format!("couldn't compare frame {} with its predecessor: {}", pair.index, e))?;
+        let mut row = self.schema.row(&record, &self.model.classify(&record.qattrs));
+        context.extend_row(&mut row);
+        Ok((row, record.timings))
+      },
+      |row: Result<(Row, MetricTimings), String>| match row {
+        Ok((row, timings)) => {
+          profiler.record(&timings);
+          if write_result.is_ok() {
+            write_result = write_row(&row);
+          }
+        }
+        Err(reason) => {
+          eprintln!("Skipping: {}", reason);
+          analysis_skipped.push(reason);
+        }
+      },
+    );
+    self.profiler.lock().unwrap().merge(&profiler);
+    if let Some(e) = source_error {
+      return Err(e).with_context(|| format!("can't decode {:?}", segment.file_path));
+    }
+    write_result?;
+    decode_skipped.extend(analysis_skipped);
+    Ok(decode_skipped)
+  }
+
+  /// Whether each segment's rows also go to the results database
+  fn stores_results(&self) -> bool {
+    #[cfg(feature = "sqlite")]
+    if self.store.is_some() {
+      return true;
+    }
+    false
+  }
+
+  /// Process the segment at `position` in the run, unless a resumed run already completed it,
+  /// recording the outcome
+  fn run_segment(&self, position: usize, seg: &SegmentDescriptor) {
+    let file_stem = seg.file_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let video_id = VideoName::parse(&seg.file_path).map_or_else(|_| file_stem.to_string(), |name| name.id);
+    let context = SegmentContext::new(seg, &video_id);
+    let key = segment_key(&context.video_id, &context.segment);
+
+    if self.args.resume && self.checkpoint.lock().unwrap().is_complete(&key) {
+      println!("already completed: {}", key);
+      self.summary.lock().unwrap().already_completed += 1;
+      return;
+    }
+
+    let mut rows = Vec::new();
+    let outcome = if let Some(problem) = self.report.blocking(seg) {
+      SegmentOutcome::Failed { reason: format!("manifest problem: {}", problem.kind) }
+    } else {
+      match self.process_segment(seg, &context, file_stem) {
+        Ok(Processed { output, rows: segment_rows, skipped }) => {
+          rows = segment_rows;
+          match output.as_deref().map(hash_file).transpose() {
+            Ok(hash) if skipped.is_empty() => SegmentOutcome::Ok { output, hash },
+            Ok(hash) => SegmentOutcome::Partial { output, hash, skipped_frames: skipped.len(), reason: skipped[0].clone() },
+            Err(e) => SegmentOutcome::Failed { reason: format!("can't hash output: {}", e) },
+          }
+        }
+        Err(e) => SegmentOutcome::Failed { reason: format!("{:#}", e) },
+      }
+    };
+    if let RunOutput::Combined(combined) = &self.output {
+      combined.lock().unwrap().add_segment(position, rows);
+    }
+
+    let mut summary = self.summary.lock().unwrap();
+    match &outcome {
+      SegmentOutcome::Ok { .. } => summary.ok += 1,
+      SegmentOutcome::Partial { skipped_frames, reason, .. } => {
+        let description = format!("skipped {} frames, first: {}", skipped_frames, reason);
+        eprintln!("Segment {} partial: {}", key, description);
+        summary.partial.push((key.clone(), description));
+      }
+      SegmentOutcome::Failed { reason } => {
+        eprintln!("Segment {} failed: {}", key, reason);
+        summary.failed.push((key.clone(), reason.clone()));
+      }
+    }
+    drop(summary);
+    let mut checkpoint = self.checkpoint.lock().unwrap();
+    checkpoint.record(&key, outcome);
+    if let Err(e) = checkpoint.save(&self.checkpoint_path) {
+      eprintln!("Unable to save checkpoint {:?}: {}", self.checkpoint_path, e);
+    }
+  }
+
+  /// Analyze one segment, into its own records file or the run's combined table
+  fn process_segment(&self, seg: &SegmentDescriptor, context: &SegmentContext, file_stem: &str)
+    -> Result<Processed>
+  {
+    println!("{} annotated? {} start: {} end: {} video: {:?}",
+             seg.kind, seg.validated_runway, seg.start_frame, seg.end_frame, seg.file_path);
+
+    // resume an interrupted run by writing out what's already stored, rather than reanalyzing it
+    #[cfg(feature = "sqlite")]
+    let mut stored = self.stored_rows(context)?;
+    #[cfg(not(feature = "sqlite"))]
+    let mut stored: Option<Vec<Row>> = None;
+    let from_store = stored.is_some();
+    if from_store {
+      println!("already stored: {} {}", context.video_id, context.segment);
+    }
+    let mut segment_rows = |write_row: &mut dyn FnMut(&Row) -> io::Result<()>| -> Result<Vec<String>> {
+      match stored.take() {
+        Some(stored) => {
+          for row in &stored {
+            write_row(row)?;
+          }
+          Ok(Vec::new())
+        }
+        None => self.analyze_segment(seg, context, write_row),
+      }
+    };
+
+    let keep_rows = self.stores_results() && !from_store;
+    let mut rows = Vec::new();
+    let (output, skipped) = match &self.output {
+      RunOutput::PerSegment => {
+        // approach outputs keep their original names
+        let kind_prefix = match seg.kind {
+          SegmentKind::Approach => "",
+          SegmentKind::Takeoff => "takeoff_",
+        };
+        let outfile_namestr = format!("abrade_{}{}-{}-{}.{}",
+                                      kind_prefix, file_stem,
+                                      seg.start_frame, seg.end_frame, self.args.output.format.extension());
+        let out_path = self.out_dir.join(outfile_namestr);
+        println!("out_path: {:?}", out_path);
+        // a resumed run redoes segments that didn't complete, replacing their partial output
+        let file = create_output_file(&out_path, self.args.output.force || self.args.resume)
+          .with_context(|| format!("can't create {:?}; --force replaces a previous run", out_path))?;
+        let mut writer = record_writer(self.args.output.format.record_format(), &self.schema,
+                                       BufWriter::new(file))?;
+        let skipped = segment_rows(&mut |row| {
+          if keep_rows {
+            rows.push(row.clone());
+          }
+          writer.write_row(row)
+        })
+          .and_then(|skipped| Ok(writer.finish().map(|_| skipped)?))
+          .with_context(|| format!("can't write {:?}", out_path))?;
+        (Some(out_path), skipped)
+      }
+      RunOutput::Combined(_) => {
+        // the rows go to the combined table once the segment completes
+        let skipped = segment_rows(&mut |row| {
+          rows.push(row.clone());
+          Ok(())
+        })?;
+        (None, skipped)
+      }
+    };
+
+    #[cfg(feature = "sqlite")]
+    if let Some(store) = self.store.as_ref().filter(|_| !from_store) {
+      store.lock().unwrap().store_segment(seg, context, &self.provenance, &rows)
+        .with_context(|| format!("can't store {} {}", context.video_id, context.segment))?;
+    }
+    Ok(Processed { output, rows, skipped })
+  }
+
+  /// The rows of a segment the results database already holds, with this run's columns,
+  /// or None if it doesn't hold the segment, holds it made with another pipeline or metrics,
+  /// or `--force` redoes stored segments
+  #[cfg(feature = "sqlite")]
+  fn stored_rows(&self, context: &SegmentContext) -> Result<Option<Vec<Row>>> {
+    let Some(store) = self.store.as_ref().filter(|_| !self.args.output.force) else {
+      return Ok(None);
+    };
+    let store = store.lock().unwrap();
+    let stored = store.load_segment(context, &self.provenance)
+      .with_context(|| format!("can't load stored {} {}", context.video_id, context.segment))?;
+    if stored.is_none() && store.has_segment(context)? {
+      println!("stored {} {} was made with a different pipeline or metrics; analyzing it again",
+               context.video_id, context.segment);
+    }
+    Ok(stored.map(|rows| rows.into_iter().map(|mut row| {
+      // z-scores aren't stored, so the frames are classified again by this run's model
+      let value = |attr: QualityAttribute| row.get(attr.name()).and_then(Value::as_f64).map(|val| val as f32);
+      if let Some(class) = self.model.classify_values(value) {
+        set_classification(&mut row, &class);
+      }
+      context.extend_row(&mut row);
+      row
+    }).collect()))
+  }
+}
+
+pub fn run(args: &SegmentsArgs) -> Result<()> {
+  let ncores = thread::available_parallelism().map_or(1, |n| n.get());
+  let njobs = args.jobs.unwrap_or(ncores).max(1);
+  // by default, share the cores between the concurrent segments
+  let nworkers = args.workers.unwrap_or(ncores / njobs).max(1);
+  let model = QualityModel::load_or_default(args.model.as_deref())
+    .with_context(|| format!("can't load quality model {:?}", args.model))?;
+  // segment columns keep per-segment tables consistent with the combined one
+  let schema = RecordSchema::new(&model).with_segment_context();
+
+  let manifest_path = args.manifest.as_path();
+  println!("manifest_path: {:?}", manifest_path);
+  let out_dir = args.output.dir_or(manifest_path.parent().unwrap_or(Path::new(".")));
+  std::fs::create_dir_all(out_dir)
+    .with_context(|| format!("can't create output directory {:?}", out_dir))?;
+
+  let manifest = Manifest::load(manifest_path)
+    .with_context(|| format!("can't load manifest {:?}", manifest_path))?;
+  // segments that can't be processed fail; the rest are checked against their frame size as they're decoded
+  let report = manifest.validate(|_| None);
+  if !report.is_clean() {
+    eprint!("{}", report);
+  }
+
+  let segments = manifest.segments();
+  println!("nsegments: {} jobs: {} workers: {}", segments.len(), njobs, nworkers);
+
+  let manifest_stem = manifest_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("manifest");
+  let checkpoint_path = args.checkpoint.clone()
+    .unwrap_or_else(|| out_dir.join(format!("{}.checkpoint.json", manifest_stem)));
+  let pipeline = args.preprocessing.load()?;
+  let mut checkpoint = if args.resume {
+    if args.output.format.combines_segments() {
+      bail!("can't resume into a combined {} file; resume with a per-segment format", args.output.format.extension());
+    }
+    let checkpoint = Checkpoint::load(&checkpoint_path)
+      .with_context(|| format!("can't load checkpoint {:?} to resume from", checkpoint_path))?;
+    if checkpoint.pipeline.as_ref().is_some_and(|recorded| *recorded != pipeline) {
+      bail!("checkpoint {:?} was made with a different preprocessing pipeline; rerun with --force instead of --resume",
+            checkpoint_path);
+    }
+    checkpoint
+  } else {
+    // a previous run's checkpoint is only replaced with --force, like its outputs
+    create_output_file(&checkpoint_path, args.output.force)
+      .with_context(|| format!("can't create checkpoint {:?}; --resume continues the previous run, --force replaces it",
+                               checkpoint_path))?;
+    Checkpoint::new(manifest_path)
+  };
+  checkpoint.pipeline = Some(pipeline.clone());
+  checkpoint.save(&checkpoint_path)
+    .with_context(|| format!("can't save checkpoint {:?}", checkpoint_path))?;
+  // beside the records, in a form `--pipeline` can read back; a resumed run's is already there,
+  // as the checkpoint showed it's the same pipeline
+  let pipeline_path = out_dir.join(format!("abrade_{}.pipeline.json", manifest_stem));
+  if !(args.resume && pipeline_path.exists()) {
+    let pipeline_file = create_output_file(&pipeline_path, args.output.force)
+      .with_context(|| format!("can't create {:?}; --force replaces a previous run", pipeline_path))?;
+    pipeline.write(pipeline_file).with_context(|| format!("can't save pipeline {:?}", pipeline_path))?;
+  }
+
+  let output = if args.output.format.combines_segments() {
+    let out_path = out_dir.join(format!("abrade_{}.{}", manifest_stem, args.output.format.extension()));
+    println!("out_path: {:?}", out_path);
+    let file = create_output_file(&out_path, args.output.force)
+      .with_context(|| format!("can't create {:?}; --force replaces a previous run", out_path))?;
+    let writer = record_writer(args.output.format.record_format(), &schema, BufWriter::new(file))?;
+    RunOutput::Combined(Mutex::new(CombinedOutput::new(writer)))
+  } else {
+    RunOutput::PerSegment
+  };
+
+  let analyzer = args.analyzer.analyzer(&model);
+  let run = Run {
+    args,
+    manifest: &manifest,
+    report,
+    #[cfg(feature = "sqlite")]
+    provenance: Provenance::new(&pipeline, analyzer.metrics(), &args.metrics.metrics),
+    analyzer,
+    pipeline,
+    model,
+    schema,
+    out_dir,
+    workers: nworkers,
+    output,
+    #[cfg(feature = "sqlite")]
+    store: match &args.db {
+      Some(db_path) => Some(Mutex::new(ResultStore::open(db_path)
+        .with_context(|| format!("can't open results database {:?}", db_path))?)),
+      None => None,
+    },
+    checkpoint: Mutex::new(checkpoint),
+    checkpoint_path,
+    summary: Mutex::new(RunSummary::default()),
+    profiler: Mutex::new(Profiler::new()),
+  };
+
+  // each worker claims the next unprocessed segment and decodes it with its own decoder
+  let next_segment = AtomicUsize::new(0);
+  thread::scope(|scope| {
+    for _ in 0..njobs.min(segments.len()) {
+      scope.spawn(|| {
+        loop {
+          let position = next_segment.fetch_add(1, Ordering::Relaxed);
+          let Some(seg) = segments.get(position) else { break };
+          run.run_segment(position, seg);
+        }
+      });
+    }
+  });
+
+  if let RunOutput::Combined(combined) = run.output {
+    combined.into_inner().unwrap().finish().context("can't write combined records")?;
+  }
+
+  if args.profile {
+    print!("{}", run.profiler.into_inner().unwrap());
+  }
+  let summary = run.summary.into_inner().unwrap();
+  println!("ok: {} partial: {} failed: {} already completed: {}",
+           summary.ok, summary.partial.len(), summary.failed.len(), summary.already_completed);
+  for (key, description) in &summary.partial {
+    println!("  partial {}: {}", key, description);
+  }
+  for (key, reason) in &summary.failed {
+    println!("  failed {}: {}", key, reason);
+  }
+  if !summary.failed.is_empty() {
+    bail!("{} of {} segments failed; rerun with --resume to retry them", summary.failed.len(), segments.len());
+  }
+  Ok(())
+}
@@ -0,0 +1,47 @@
+//! Fit a vignetting model from a batch of full (uncropped) frames
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use image::GrayImage;
+use vorgon::preprocessing::Pipeline;
+use vorgon::vignetting::VignettingModel;
+
+
+#[derive(Args, Debug)]
+pub struct VignettingArgs {
+  /// Where to save the fitted model (JSON)
+  pub model: PathBuf,
+  /// Preprocessing pipeline config whose channel mix the model will correct;
+  /// without one, frames are mixed to red-green luma as by default
+  #[arg(long)]
+  pub pipeline: Option<PathBuf>,
+  /// Uncropped frame images of varied scenes, eg from `vorgon extract --crop 1`
+  #[arg(required = true)]
+  pub images: Vec<PathBuf>,
+}
+
+pub fn run(args: &VignettingArgs) -> Result<()> {
+  let pipeline = match &args.pipeline {
+    Some(path) => Pipeline::load(path).with_context(|| format!("can't load pipeline {:?}", path))?,
+    None => Pipeline::default(),
+  };
+  let mut frames: Vec<GrayImage> = Vec::new();
+  for image_path in &args.images {
+    match image::open(image_path) {
+      // the same gray as preprocessing, which the model will correct
+      Ok(img) => frames.push(pipeline.mix_to_gray(&img.into_rgb8())),
+      Err(e) => eprintln!("Unable to open {:?}: {}", image_path, e),
+    }
+  }
+  println!("nframes: {}", frames.len());
+
+  let model = VignettingModel::fit(&frames)
+    .context("need at least one readable frame that isn't black")?;
+  println!("coefficients: {:?}", model.coefficients);
+  for r in [0.5, 0.8, 1.0] {
+    println!("gain at radius {}: {:0.3}", r, model.gain(r));
+  }
+  model.save(&args.model).with_context(|| format!("can't save vignetting model {:?}", args.model))
+}
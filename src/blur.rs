@@ -0,0 +1,142 @@
+//! Telling motion blur from defocus: the direction and length of a frame's blur.
+//!
+//! Linear motion smears each point into a streak, suppressing gradients along the motion
+//! and leaving those across it, so the gradients of a motion-blurred frame are anisotropic.
+//! The derivative along the motion is then the difference of two copies of the frame offset by
+//! the streak length, so its autocorrelation dips negative at that lag. Defocus blurs every
+//! direction alike: its gradients stay isotropic, and the dip comes at about the blur's width.
+
+use image::GrayImage;
+use imageproc::gradients::{horizontal_sobel, vertical_sobel};
+
+/// Longest blur streak looked for, in pixels
+pub const MAX_BLUR_EXTENT: usize = 32;
+
+/// Normalized autocorrelation of the directional derivative below which a dip marks the streak length.
+/// A sharp frame's derivative is only anti-correlated with its immediate neighbour.
+const STREAK_CORRELATION: f64 = -0.1;
+
+/// Autocorrelation is sampled on a grid with this spacing, in pixels
+const SAMPLE_SPACING: usize = 2;
+
+/// Dominant direction and extent of a frame's blur
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BlurEstimate {
+  /// Direction of the blur in degrees, in [0, 180), from the image x axis towards its y axis
+  /// (clockwise on screen, as image rows run downwards)
+  pub direction: f64,
+  /// Coherence of the gradient orientations: 0 when they're isotropic, as for defocus (or no blur),
+  /// up to 1 when they're all across one direction. Strongly oriented scenes, like runway markings, raise it too.
+  pub anisotropy: f64,
+  /// Length of the blur along `direction`, in pixels: the streak length of motion blur,
+  /// or roughly the width of a defocus blur; 0 if none was found
+  pub extent: f64,
+}
+
+/// Estimate the blur of an image. Images too small to measure get the default (no blur).
+pub fn estimate_blur(img: &GrayImage) -> BlurEstimate {
+  let (width, height) = (img.width() as usize, img.height() as usize);
+  if width <= 2 * (MAX_BLUR_EXTENT + 2) || height <= 2 * (MAX_BLUR_EXTENT + 2) {
+    return BlurEstimate::default();
+  }
+
+  // structure tensor of the whole frame
+  let gx = horizontal_sobel(img);
+  let gy = vertical_sobel(img);
+  let (mut jxx, mut jxy, mut jyy) = (0.0, 0.0, 0.0);
+  for (dx, dy) in gx.pixels().zip(gy.pixels()) {
+    let (dx, dy) = (dx.0[0] as f64, dy.0[0] as f64);
+    jxx += dx * dx;
+    jxy += dx * dy;
+    jyy += dy * dy;
+  }
+  let trace = jxx + jyy;
+  if trace == 0.0 {
+    return BlurEstimate::default();
+  }
+  let anisotropy = ((jxx - jyy).powi(2) + 4.0 * jxy * jxy).sqrt() / trace;
+  // the gradients mostly lie across the blur
+  let gradient_angle = 0.5 * (2.0 * jxy).atan2(jxx - jyy);
+  let blur_angle = gradient_angle + std::f64::consts::FRAC_PI_2;
+  let direction = blur_angle.to_degrees().rem_euclid(180.0);
+
+  BlurEstimate { direction, anisotropy, extent: streak_length(img, blur_angle) }
+}
+
+/// Intensity at a point between pixels, interpolated bilinearly
+fn sample(img: &GrayImage, x: f64, y: f64) -> f64 {
+  let (x0, y0) = (x.floor(), y.floor());
+  let (fx, fy) = (x - x0, y - y0);
+  let (x0, y0) = (x0 as u32, y0 as u32);
+  let at = |x: u32, y: u32| img.get_pixel(x, y).0[0] as f64;
+  let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+  let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+  top * (1.0 - fy) + bottom * fy
+}
+
+/// The lag, up to `MAX_BLUR_EXTENT`, at which the derivative along `angle` (radians) is most
+/// anti-correlated with itself, or 0 if it never dips below `STREAK_CORRELATION`
+fn streak_length(img: &GrayImage, angle: f64) -> f64 {
+  let (width, height) = (img.width() as usize, img.height() as usize);
+  let (ux, uy) = (angle.cos(), angle.sin());
+  // keep every sample along the streak inside the image, with room to interpolate
+  let margin = MAX_BLUR_EXTENT + 2;
+
+  let mut correlation = [0.0; MAX_BLUR_EXTENT + 1];
+  let mut profile = [0.0; MAX_BLUR_EXTENT + 2];
+  for y in (margin..height - margin).step_by(SAMPLE_SPACING) {
+    for x in (margin..width - margin).step_by(SAMPLE_SPACING) {
+      for (step, val) in profile.iter_mut().enumerate() {
+        *val = sample(img, x as f64 + step as f64 * ux, y as f64 + step as f64 * uy);
+      }
+      let derivative = profile[1] - profile[0];
+      for (lag, sum) in correlation.iter_mut().enumerate() {
+        *sum += derivative * (profile[lag + 1] - profile[lag]);
+      }
+    }
+  }
+  if correlation[0] <= 0.0 {
+    return 0.0;
+  }
+
+  // lag 1 is anti-correlated in any frame, so a streak must be longer
+  let (lag, dip) = correlation.iter().enumerate().skip(2)
+    .map(|(lag, sum)| (lag, sum / correlation[0]))
+    .fold((0, 0.0), |lowest, (lag, val)| if val < lowest.1 { (lag, val) } else { lowest });
+  if dip < STREAK_CORRELATION { lag as f64 } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+  use imageproc::filter::gaussian_blur_f32;
+  use imageproc::noise::gaussian_noise;
+
+  use super::*;
+
+  fn texture() -> GrayImage {
+    gaussian_noise(&GrayImage::from_pixel(160, 160, image::Luma([128])), 0.0, 40.0, 5)
+  }
+
+  /// Average each pixel with the `length - 1` following it along x, clamped at the right edge
+  fn box_blur_x(img: &GrayImage, length: u32) -> GrayImage {
+    GrayImage::from_fn(img.width(), img.height(), |x, y| {
+      let sum: u32 = (x..x + length).map(|x| img.get_pixel(x.min(img.width() - 1), y).0[0] as u32).sum();
+      image::Luma([(sum / length) as u8])
+    })
+  }
+
+  #[test]
+  fn horizontal_motion_blur_is_found() {
+    let blur = estimate_blur(&box_blur_x(&texture(), 9));
+    let off_axis = blur.direction.min(180.0 - blur.direction);
+    assert!(off_axis < 5.0, "direction {}", blur.direction);
+    assert!(blur.anisotropy > 0.5, "anisotropy {}", blur.anisotropy);
+    assert!((blur.extent - 9.0).abs() <= 1.0, "extent {}", blur.extent);
+  }
+
+  #[test]
+  fn defocus_is_isotropic() {
+    let blur = estimate_blur(&gaussian_blur_f32(&texture(), 2.0));
+    assert!(blur.anisotropy < 0.1, "anisotropy {}", blur.anisotropy);
+  }
+}
@@ -0,0 +1,182 @@
+//! Progress of a batch run over many segments, saved so an interrupted run can resume
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::preprocessing::Pipeline;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64-bit FNV-1a hash of everything `reader` yields
+pub fn fnv1a_64<R: Read>(mut reader: R) -> io::Result<u64> {
+  let mut hash = FNV_OFFSET_BASIS;
+  let mut buf = [0u8; 64 * 1024];
+  loop {
+    let nread = match reader.read(&mut buf) {
+      Ok(0) => return Ok(hash),
+      Ok(nread) => nread,
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e),
+    };
+    for byte in &buf[..nread] {
+      hash ^= *byte as u64;
+      hash = hash.wrapping_mul(FNV_PRIME);
+    }
+  }
+}
+
+/// Hash of a file's contents, as recorded in checkpoints
+pub fn hash_file(path: &Path) -> io::Result<String> {
+  let hash = fnv1a_64(BufReader::new(File::open(path)?))?;
+  Ok(format!("fnv1a64:{:016x}", hash))
+}
+
+/// Checkpoint key of a segment: `<video id>/<segment>`
+pub fn segment_key(video_id: &str, segment: &str) -> String {
+  format!("{}/{}", video_id, segment)
+}
+
+/// How the latest attempt at a segment ended
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SegmentOutcome {
+  /// Every frame was analyzed
+  Ok {
+    /// The segment's own output file, if it has one
+    output: Option<PathBuf>,
+    hash: Option<String>,
+  },
+  /// Completed, but some frames couldn't be decoded or analyzed
+  Partial {
+    output: Option<PathBuf>,
+    hash: Option<String>,
+    skipped_frames: usize,
+    /// The first frame error
+    reason: String,
+  },
+  Failed {
+    reason: String,
+  },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CheckpointEntry {
+  pub outcome: SegmentOutcome,
+  pub attempts: u32,
+  /// RFC 3339 local time of the latest attempt
+  pub updated: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Checkpoint {
+  pub manifest: PathBuf,
+  /// How frames were preprocessed, so a resumed run can't mix pipelines
+  #[serde(default)]
+  pub pipeline: Option<Pipeline>,
+  /// Keyed by `segment_key`
+  pub segments: BTreeMap<String, CheckpointEntry>,
+}
+
+impl Checkpoint {
+  pub fn new(manifest: &Path) -> Self {
+    Self { manifest: manifest.to_path_buf(), pipeline: None, segments: BTreeMap::new() }
+  }
+
+  pub fn load(path: &Path) -> io::Result<Self> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+  }
+
+  /// Save by replacing the previous checkpoint, so a crash mid-save can't leave it truncated
+  pub fn save(&self, path: &Path) -> io::Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    {
+      let mut writer = BufWriter::new(File::create(&tmp_path)?);
+      serde_json::to_writer_pretty(&mut writer, self)?;
+      writer.flush()?;
+    }
+    fs::rename(&tmp_path, path)
+  }
+
+  /// Whether every frame of a segment was analyzed, and its output (if any) is unchanged since.
+  /// Partial segments aren't complete, so a resumed run retries their skipped frames.
+  pub fn is_complete(&self, key: &str) -> bool {
+    match self.segments.get(key).map(|entry| &entry.outcome) {
+      Some(SegmentOutcome::Ok { output: Some(output), hash }) => hash.is_some() && hash_file(output).ok() == *hash,
+      Some(SegmentOutcome::Ok { output: None, .. }) => true,
+      _ => false,
+    }
+  }
+
+  /// Record the outcome of an attempt at a segment
+  pub fn record(&mut self, key: &str, outcome: SegmentOutcome) {
+    let updated = chrono::Local::now().to_rfc3339();
+    let entry = self.segments.entry(key.to_string())
+      .or_insert_with(|| CheckpointEntry { outcome: outcome.clone(), attempts: 0, updated: updated.clone() });
+    entry.outcome = outcome;
+    entry.attempts += 1;
+    entry.updated = updated;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn recorded_outcomes_survive_a_save() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("run.checkpoint.json");
+    let mut checkpoint = Checkpoint::new(Path::new("approaches.json"));
+    checkpoint.pipeline = Some(Pipeline::default());
+    checkpoint.record("v1/100-200", SegmentOutcome::Failed { reason: "can't decode".to_string() });
+    let outcome = SegmentOutcome::Ok { output: None, hash: None };
+    checkpoint.record("v1/100-200", outcome.clone());
+    checkpoint.save(&path).unwrap();
+
+    let loaded = Checkpoint::load(&path).unwrap();
+    assert_eq!(loaded.manifest, Path::new("approaches.json"));
+    assert_eq!(loaded.pipeline, Some(Pipeline::default()));
+    let entry = &loaded.segments["v1/100-200"];
+    assert_eq!(entry.outcome, outcome);
+    assert_eq!(entry.attempts, 2);
+    assert!(!path.with_extension("json.tmp").exists());
+  }
+
+  #[test]
+  fn complete_only_while_the_output_is_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("abrade_v1-100-200.csv");
+    fs::write(&output, "frame\n100\n").unwrap();
+    let hash = Some(hash_file(&output).unwrap());
+    let mut checkpoint = Checkpoint::new(Path::new("approaches.json"));
+    checkpoint.record("v1/100-200", SegmentOutcome::Ok { output: Some(output.clone()), hash });
+    assert!(checkpoint.is_complete("v1/100-200"));
+    assert!(!checkpoint.is_complete("v1/300-400"));
+
+    fs::write(&output, "frame\n100\n101\n").unwrap();
+    assert!(!checkpoint.is_complete("v1/100-200"));
+    fs::remove_file(&output).unwrap();
+    assert!(!checkpoint.is_complete("v1/100-200"));
+  }
+
+  #[test]
+  fn partial_and_failed_segments_are_retried() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("abrade_v1-100-200.csv");
+    fs::write(&output, "frame\n100\n").unwrap();
+    let hash = Some(hash_file(&output).unwrap());
+    let mut checkpoint = Checkpoint::new(Path::new("approaches.json"));
+    checkpoint.record("v1/100-200", SegmentOutcome::Partial {
+      output: Some(output), hash, skipped_frames: 1, reason: "frame 101: corrupt packet".to_string(),
+    });
+    checkpoint.record("v1/300-400", SegmentOutcome::Failed { reason: "can't decode".to_string() });
+    assert!(!checkpoint.is_complete("v1/100-200"));
+    assert!(!checkpoint.is_complete("v1/300-400"));
+  }
+}
@@ -1,12 +1,14 @@
 
 
+use std::collections::BTreeSet;
+use std::ops::Deref;
+use std::time::Instant;
+
 use image::{DynamicImage, imageops::crop_imm};
+use serde::Serialize;
 // use image::buffer::ConvertBuffer;
 
-use image_compare::{
-  Metric,
-  prelude::*,
-};
+use image_compare::prelude::*;
 
 use imageproc::{
   corners::corners_fast12,
@@ -19,15 +21,63 @@ use imageproc::{
 };
 use imageproc::corners::corners_fast9;
 
+use crate::analyzer::{Analyzer, ImageMetric, MetricTimings};
+use crate::metrics::ComparisonMetric;
+use crate::preprocessing::Pipeline;
+
+pub mod analyzer;
+pub mod artifacts;
+pub mod blur;
+pub mod checkpoint;
+pub mod manifest;
+pub mod metrics;
+pub mod noise;
+pub mod output;
+#[cfg(feature = "parquet")]
+pub mod parquet_export;
+pub mod pipeline;
+pub mod preprocessing;
+pub mod profile;
+pub mod quality;
+pub mod records;
+pub mod sequence;
+pub mod sharpness;
+#[cfg(feature = "sqlite")]
+pub mod store;
+pub mod video;
+pub mod vignetting;
+
 /// Describes the "inherent" quality of a single-channel image
 /// with no reference to another image.
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 #[derive(Default)]
 pub struct MonoImageQAttributes {
   pub width: u32,
   pub height: u32,
   /// Measurement of sharpness, maybe Laplacian variance or zero
   pub sharpness: f32,
+  /// Variance of the signed Laplacian
+  pub laplacian_variance: f64,
+  /// Mean squared Sobel gradient
+  pub tenengrad: f64,
+  /// Brenner gradient
+  pub brenner: f64,
+  /// Fraction of spectral power at high frequencies
+  pub hf_ratio: f64,
+  /// Dominant blur direction, degrees clockwise from the x axis
+  pub blur_direction: f64,
+  /// How directional the blur is: near 0 for defocus, towards 1 for motion blur
+  pub blur_anisotropy: f64,
+  /// Length of the blur, in pixels
+  pub blur_extent: f64,
+  /// Estimated standard deviation of the sensor noise, in gray levels
+  pub noise_sigma: f64,
+  /// Blockiness on an 8x8 grid: about 1 without blocking
+  pub blockiness_8: f64,
+  /// Blockiness on a 16x16 (macroblock) grid
+  pub blockiness_16: f64,
+  /// Excess activity beside strong edges, in gray levels
+  pub ringing: f64,
   /// The mean intensity of all pixels
   pub mean_intensity: u8,
   /// Histogram spread
@@ -50,6 +100,23 @@ pub struct MonoImageQAttributes {
   pub corner_count_f9: u32,
   // Raw histogram
   // pub raw_histogram: [u32; 256],
+  /// The metrics that filled in these attributes; the fields of others are zero
+  #[serde(skip)]
+  pub measured: BTreeSet<ImageMetric>,
+}
+
+/// Find the entry of `all` called `name`, accepting `-` for `_`, for the `FromStr` of a named enum.
+/// `kind` says what's being parsed, in the error listing the valid names.
+pub(crate) fn parse_name<T: Copy>(name: &str, all: &[T], name_of: fn(&T) -> &'static str, kind: &str)
+  -> Result<T, String>
+{
+  let name = name.replace('-', "_");
+  all.iter().copied()
+    .find(|item| name_of(item) == name)
+    .ok_or_else(|| {
+      let names: Vec<&str> = all.iter().map(name_of).collect();
+      format!("unknown {} {:?}, expected one of {}", kind, name, names.join(", "))
+    })
 }
 
 /// Crop image to some percentage of its original dimensions:
@@ -95,7 +162,8 @@ pub fn crop_rgb_to_percent(raw_img: &RgbImage, percent: f32) -> RgbImage
 }
 
 
-/// One way to measure sharpness
+/// One way to measure sharpness; the filter output is clamped to u8, losing negative responses.
+/// See `sharpness` for measures comparable across image sizes.
 pub fn laplacian_variance(image: &GrayImage) -> (GrayImage, f32) {
   // Define the Laplacian kernel
   let laplacian_kernel: [f32; 9] = [0.0, -1.0, 0.0, -1.0, 4.0, -1.0, 0.0, -1.0, 0.0];
@@ -127,56 +195,6 @@ pub fn laplacian_variance(image: &GrayImage) -> (GrayImage, f32) {
   (filtered_image, score)
 }
 
-/// Returns (mean_intensity, histogram_spread, histogram_flatness)
-pub fn hist_mean_spread_flatness(image: &GrayImage, qattrs: &mut MonoImageQAttributes)
-{
-  let channel_hist = imageproc::stats::histogram(&image);
-  let mut min_intensity = u8::MAX;
-  let mut max_intensity = u8::MIN;
-  let mut total_intensity: usize = 0;
-  let mut cumulative_count: usize = 0;
-  let mut first_quartile = 0;
-  let mut third_quartile = 0;
-  let mut hfm = 0.0; // Histogram Flatness Measure
-
-  // TODO don't we already know the total number of pixels already from image size
-  let total_pixels: usize = (image.width() * image.height()) as usize;
-
-  if let Some(hist) = channel_hist.channels.first() {
-    let total_pixels_f64 = total_pixels as f64;
-    let first_quartile_count = total_pixels / 4;
-    let third_quartile_count =  3 * total_pixels / 4;
-
-    for i in 0..256 {
-      let count = hist[i] as usize;
-      if count > 0 {
-        let intensity = i as u8;
-        if intensity > max_intensity { max_intensity = intensity; }
-        if intensity < min_intensity { min_intensity = intensity; }
-        total_intensity += count * (intensity as usize);
-        // flatness calculation
-        let probability = count as f64 / total_pixels_f64;
-        hfm -= probability * probability.log2(); // calculate entropy
-        // spreading calculation
-        cumulative_count += count;
-        if (first_quartile == 0) && (cumulative_count >= first_quartile_count) {
-          first_quartile = i;
-        }
-        if (third_quartile == 0) && (cumulative_count >= third_quartile_count) {
-          third_quartile = i;
-        }
-      }
-    }
-  }
-
-  let quartile_distance = third_quartile  - first_quartile ;
-  qattrs.hist_spread = (quartile_distance as f64) / 255.0;
-  qattrs.mean_intensity  = f32::round(total_intensity as f32 / total_pixels as f32) as u8;
-  qattrs.hist_flatness = hfm;
-  // qattrs.raw_histogram = channel_hist;
-
-}
-
 /// Find the top few peaks in the histogram
 pub fn find_peaks_in_histogram(hist: &[u32; 256]) -> Vec<(u8, u32)> {
   let mut peaks = vec![];
@@ -220,7 +238,9 @@ pub fn find_peaks_in_histogram(hist: &[u32; 256]) -> Vec<(u8, u32)> {
 }
 
 /// Pull a single channel out of an RgbImage, as a GrayImage
-pub fn mono_as_grey(input: &ImageBuffer<Rgb<u8>, &[u8]>, channel: usize) -> GrayImage {
+pub fn mono_as_grey<C>(input: &ImageBuffer<Rgb<u8>, C>, channel: usize) -> GrayImage
+  where C: Deref<Target = [u8]>
+{
   let mut output: GrayImage = GrayImage::new(input.width(), input.height());
   for (out_pixel, in_pixel) in output.pixels_mut().zip(input.pixels()) {
     out_pixel.0[0] = in_pixel.0[channel];
@@ -229,7 +249,9 @@ pub fn mono_as_grey(input: &ImageBuffer<Rgb<u8>, &[u8]>, channel: usize) -> Gray
 }
 
 /// Combine red and green channels to obtain a combined luma
-pub fn red_green_as_grey(input: &ImageBuffer<Rgb<u8>, &[u8]>) -> GrayImage {
+pub fn red_green_as_grey<C>(input: &ImageBuffer<Rgb<u8>, C>) -> GrayImage
+  where C: Deref<Target = [u8]>
+{
   // refer to rgb_to_luma -- this is an inaccurate sRGB conversion
   let mut output: GrayImage = GrayImage::new(input.width(), input.height());
   for (out_pixel, in_pixel) in output.pixels_mut().zip(input.pixels()) {
@@ -239,152 +261,28 @@ pub fn red_green_as_grey(input: &ImageBuffer<Rgb<u8>, &[u8]>) -> GrayImage {
 }
 
 
-///
-pub fn preprocess_rgb_to_gray(input: &ImageBuffer<Rgb<u8>, &[u8]>) -> GrayImage
+/// Red-green luma, cropped to remove vignetting: the default `Pipeline`
+pub fn preprocess_rgb_to_gray<C>(input: &ImageBuffer<Rgb<u8>, C>) -> GrayImage
+  where C: Deref<Target = [u8]>
 {
-  let work_img = red_green_as_grey(&input);
-  // let work_img: GrayImage = input.convert();
-
-  // inject some noise
-  // let work_img = imageproc::noise::gaussian_noise(
-  //   &work_img,21.0, 10.0, 555666777888);
-  // let work_img= imageproc::noise::salt_and_pepper_noise(
-  //   &work_img, 1E-4, 888777666555);
-
-  // let work_img = imageproc::filter::gaussian_blur_f32(&work_img,  1.0);
-  // let work_img = imageproc::filter::bilateral_filter(&work_img,8, 2.0, 1.0);
-
-  // remove vignetting
-  let work_img = crop_gray_to_percent(&work_img, 0.8);
-
-  // let work_img = imageproc::contrast::stretch_contrast(&work_img, 20, 235);
-  // let work_img = imageproc::contrast::equalize_histogram(&work_img);
-
-  work_img
-
+  Pipeline::default().apply(input)
 }
 
-/// Performs histogram analysis
-pub fn fast_histogram_analysis(image: &GrayImage, qattrs: &mut MonoImageQAttributes)
+/// Like `preprocess_rgb_to_gray`, but keeping `crop_percent` of each dimension
+pub fn preprocess_rgb_to_gray_with_crop<C>(input: &ImageBuffer<Rgb<u8>, C>, crop_percent: f32) -> GrayImage
+  where C: Deref<Target = [u8]>
 {
-  let channel_hist = imageproc::stats::histogram(&image);
-  let mut min_intensity = u8::MAX;
-  let mut max_intensity = u8::MIN;
-  let mut total_intensity: usize = 0;
-  let mut cumulative_count: usize = 0;
-  let mut first_quartile = 0;
-  let mut third_quartile = 0;
-
-  let total_pixels: usize = (image.width() * image.height()) as usize;
-
-  if let Some(hist) = channel_hist.channels.first() {
-    // let lum_peaks = find_peaks_in_histogram(&hist);
-    // println!("lum_peaks: {:?}", lum_peaks);
-
-    let first_quartile_count = total_pixels / 4;
-    let third_quartile_count =  3 * total_pixels / 4;
-
-    for i in 0..256 {
-      let count = hist[i] as i32;
-      if count > 0 {
-        let intensity = i as u8;
-        if intensity > max_intensity { max_intensity = intensity; }
-        if intensity < min_intensity { min_intensity = intensity; }
-        total_intensity +=  (count as usize) * i ;
-        // a gaussian distribution centered at 127.5
-        // will have exceptional pixels within 1 stddev (255/6) of min and max
-        if intensity < 43 { qattrs.dark_pixel_count += count as u32; }
-        else if intensity > (u8::MAX - 43) { qattrs.bright_pixel_count += count as u32; }
-
-        // histogram spreading calculation
-        cumulative_count += count as usize;
-        if (first_quartile == 0) && (cumulative_count >= first_quartile_count) {
-          first_quartile = i;
-        }
-        if (third_quartile == 0) && (cumulative_count >= third_quartile_count) {
-          third_quartile = i;
-        }
-      }
-    }
-  }
-
-  let total_pix_f32 = total_pixels as f32;
-  qattrs.dark_percent = qattrs.dark_pixel_count as f32 / total_pix_f32;
-  qattrs.bright_percent = qattrs.bright_pixel_count as f32 / total_pix_f32;
-
-  let quartile_distance = third_quartile  - first_quartile ;
-  // TODO should we actually use (max_intensity - min_intensity) for divisor (range)?
-  qattrs.hist_spread = (quartile_distance as f64) / 255.0;
-  qattrs.mean_intensity = f32::round(total_intensity as f32 / total_pix_f32) as u8;
-  // qattrs.raw_histogram = channel_hist;
+  Pipeline::cropped(crop_percent, None).apply(input)
 }
 
-
-
 /// Measure the no-reference quality attributes of an image
 pub fn analyze_image(img: &GrayImage)  -> MonoImageQAttributes {
-  let mut durations: Vec<u32> = Vec::new();
-  let mut tsms:i64 = 0;
-
-  let mut qattrs = MonoImageQAttributes::default();
-  qattrs.width = img.width();
-  qattrs.height = img.height();
-
-  timest(&mut tsms);
-  // // println!("{} >> start sharpness ",  timest(&mut tsms));
-  let (_laplace_img, sharpness ) = laplacian_variance(&img);
-  qattrs.sharpness = sharpness;
-  // // println!("{} << end sharpness ",  timest(&mut tsms));
-  timex(&mut tsms, &mut durations);
-
-  timest(&mut tsms);
-  // println!("{} >> start histo ",  timest(&mut tsms));
-  hist_mean_spread_flatness(&img, &mut qattrs);
-  // println!("{} << end histo ",  timest(&mut tsms));
-  timex(&mut tsms, &mut durations);
-
-  timest(&mut tsms);
-  // println!("{} >> start corners ",  timest(&mut tsms));
-  qattrs.corner_count_f12 = count_corners_fast12(&img);
-  // println!("{} << end corners ",  timest(&mut tsms));
-  timex(&mut tsms, &mut durations);
-
-  timest(&mut tsms);
-  // println!("{} >> start corners ",  timest(&mut tsms));
-  qattrs.corner_count_f9 = count_corners_fast9(&img);
-  // println!("{} << end corners ",  timest(&mut tsms));
-  timex(&mut tsms, &mut durations);
-
-  // println!("analyze durations {:?}", durations);
-
-  qattrs
+  Analyzer::full().analyze(img).qattrs
 }
 
 /// Measure the key no-reference quality attributes of an image -- fast
 pub fn fast_analyze_image(img: &GrayImage)  -> MonoImageQAttributes {
-  let mut durations: Vec<u32> = Vec::new();
-  let mut tsms:i64 = 0;
-
-  let mut qattrs = MonoImageQAttributes::default();
-  qattrs.width = img.width();
-  qattrs.height = img.height();
-
-  timest(&mut tsms);
-  // println!("{} >> start histo ",  timest(&mut tsms));
-  fast_histogram_analysis(&img, &mut qattrs);
-  // println!("{} << end histo ",  timest(&mut tsms));
-  timex(&mut tsms, &mut durations);
-
-  // For fast analysis, limit to one or the other corner detector?
-  timest(&mut tsms);
-  // println!("{} >> start corners ",  timest(&mut tsms));
-  qattrs.corner_count_f12 = count_corners_fast12(&img);
-  // println!("{} << end corners ",  timest(&mut tsms));
-  timex(&mut tsms, &mut durations);
-
-  // println!("analyze durations {:?}", durations);
-
-  qattrs
+  Analyzer::fast().analyze(img).qattrs
 }
 
 /// Estimate the maximum number of corners that could be detected in an
@@ -412,72 +310,97 @@ pub fn count_corners_fast9(img: &GrayImage) -> u32 {
 
 /// Represents a comparison between two images,
 /// where one image provides a reference for comparison.
-#[derive(Debug)]
+/// Metrics that weren't measured are None.
+#[derive(Debug, Serialize)]
 #[derive(Default)]
 pub struct ImgComparison {
-  pub rms_error: f64,
-  pub ssim_score: f64,
-  pub hsim_score: f64,
+  pub rms_error: Option<f64>,
+  /// Peak signal-to-noise ratio, dB
+  pub psnr: Option<f64>,
+  pub ssim_score: Option<f64>,
+  /// Multi-scale SSIM
+  pub ms_ssim_score: Option<f64>,
+  /// Gradient magnitude similarity deviation
+  pub gmsd: Option<f64>,
+  /// Histogram correlation
+  pub hsim_score: Option<f64>,
+  pub hellinger_distance: Option<f64>,
+  pub chi_square_distance: Option<f64>,
+  pub bhattacharyya_distance: Option<f64>,
+}
+
+impl ImgComparison {
+  /// The value of a metric, if it was measured
+  pub fn get(&self, metric: ComparisonMetric) -> Option<f64> {
+    match metric {
+      ComparisonMetric::Rmse => self.rms_error,
+      ComparisonMetric::Psnr => self.psnr,
+      ComparisonMetric::Ssim => self.ssim_score,
+      ComparisonMetric::MsSsim => self.ms_ssim_score,
+      ComparisonMetric::Gmsd => self.gmsd,
+      ComparisonMetric::HistCorrelation => self.hsim_score,
+      ComparisonMetric::Hellinger => self.hellinger_distance,
+      ComparisonMetric::ChiSquare => self.chi_square_distance,
+      ComparisonMetric::Bhattacharyya => self.bhattacharyya_distance,
+    }
+  }
+
+  pub fn set(&mut self, metric: ComparisonMetric, value: f64) {
+    let slot = match metric {
+      ComparisonMetric::Rmse => &mut self.rms_error,
+      ComparisonMetric::Psnr => &mut self.psnr,
+      ComparisonMetric::Ssim => &mut self.ssim_score,
+      ComparisonMetric::MsSsim => &mut self.ms_ssim_score,
+      ComparisonMetric::Gmsd => &mut self.gmsd,
+      ComparisonMetric::HistCorrelation => &mut self.hsim_score,
+      ComparisonMetric::Hellinger => &mut self.hellinger_distance,
+      ComparisonMetric::ChiSquare => &mut self.chi_square_distance,
+      ComparisonMetric::Bhattacharyya => &mut self.bhattacharyya_distance,
+    };
+    *slot = Some(value);
+  }
 }
 
 
-/// Image comparisons
+/// Image comparisons with the default metrics. Fails if the images differ in size.
 pub fn compare_images(img1: &GrayImage, img2: &GrayImage, gen_map: bool)
-  -> (ImgComparison, Option<DynamicImage>)
+  -> Result<(ImgComparison, Option<DynamicImage>), CompareError>
 {
-  let mut durations: Vec<u32> = Vec::new();
-  let mut tsms:i64 = 0;
+  compare_images_with(img1, img2, &ComparisonMetric::DEFAULT_SET, gen_map)
+}
+
+/// Compare `img2` with the reference `img1` by each of `metrics`,
+/// optionally with a color map of their local structural similarity
+pub fn compare_images_with(img1: &GrayImage, img2: &GrayImage, metrics: &[ComparisonMetric], gen_map: bool)
+  -> Result<(ImgComparison, Option<DynamicImage>), CompareError>
+{
+  let (comparison, color_map, _timings) = compare_images_timed(img1, img2, metrics, gen_map)?;
+  Ok((comparison, color_map))
+}
+
+/// `compare_images_with`, also returning how long each metric (and the color map) took
+pub fn compare_images_timed(img1: &GrayImage, img2: &GrayImage, metrics: &[ComparisonMetric], gen_map: bool)
+  -> Result<(ImgComparison, Option<DynamicImage>, MetricTimings), CompareError>
+{
+  let mut timings = MetricTimings::with_capacity(metrics.len() + 1);
   let mut comparison = ImgComparison::default();
 
-  // timest(&mut tsms);
-  // // println!("{} >> start RMS:",timest(&mut tsms));
-  // comparison.rms_error = imageproc::stats::root_mean_squared_error(img1, img2);
-  // // println!("{} << end RMS ",  timex(&mut tsms, &mut durations));
-  // timex(&mut tsms, &mut durations);
-
-  timest(&mut tsms);
-  // println!("{} >> start SSIM", timest(&mut tsms));
-  let ssim = image_compare::gray_similarity_structure(
-    &image_compare::Algorithm::MSSIMSimple, &img1, &img2).unwrap();
-  // println!("{} << end SSIM", timex(&mut tsms, &mut durations));
-  comparison.ssim_score = ssim.score;
-  timex(&mut tsms, &mut durations);
-
-  timest(&mut tsms);
-  //println!("{} >> start HSIM", timest(&mut tsms));
-  comparison.hsim_score = image_compare::gray_similarity_histogram(
-    // Metric::Hellinger, &img1, &img2).unwrap();
-    Metric::Correlation, &img1, &img2).unwrap();
-  // println!("{} << end HSIM", timex(&mut tsms, &mut durations));
-  timex(&mut tsms, &mut durations);
+  for metric in metrics {
+    let start = Instant::now();
+    comparison.set(*metric, metric.measure(img1, img2)?);
+    timings.push((metric.name(), start.elapsed()));
+  }
 
   let ssim_color_map = if gen_map {
-    timest(&mut tsms);
-    // println!("{} >> start color_map", timest(&mut tsms));
+    let start = Instant::now();
+    let ssim = image_compare::gray_similarity_structure(
+      &image_compare::Algorithm::MSSIMSimple, img1, img2)?;
     let color_map = ssim.image.to_color_map();
-    // println!("{} << end color_map", timex(&mut tsms, &mut durations));
-    timex(&mut tsms, &mut durations);
+    timings.push(("ssim_color_map", start.elapsed()));
     Some(color_map)
   } else { None };
 
-  // println!("{}",comparison);
-  // println!("compare durations{:?}", durations);
-  (comparison, ssim_color_map)
-}
-
-
-
-fn timest(last: &mut i64) -> i64 {
-  let cur = chrono::Local::now().timestamp_millis();
-  let delta = cur - *last;
-  *last = cur;
-  delta
-}
-
-fn timex(last: &mut i64, durations: &mut Vec<u32>) -> u32 {
-  let duration = timest(last) as u32;
-  durations.push(duration );
-  duration
+  Ok((comparison, ssim_color_map, timings))
 }
 
 //fn histogram_spread(histogram: [u32; 256]) -> f32 {
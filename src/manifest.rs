@@ -0,0 +1,536 @@
+//! The approaches manifest: annotated segments of flight videos, keyed by flight timestamp
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Keypoint {
+  pub frame: u32,
+  pub x1: u32,
+  pub y1: u32,
+  pub x2: u32,
+  pub y2: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BoundingBox {
+  pub frame: u32,
+  pub tl_x: u32,
+  pub tl_y: u32,
+  pub br_x: u32,
+  pub br_y: u32,
+}
+
+/// A stretch of video showing the aircraft on a runway, with optional runway annotations
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunwaySegment {
+  /// Video file name, without the `.mp4` extension
+  pub stream: String,
+  pub start_frame: u32,
+  pub end_frame: u32,
+  pub icao: String,
+  pub runway_designator: String,
+  pub annotated_keypoints: Option<Keypoint>,
+  pub annotated_bbox: Option<BoundingBox>,
+  /// Set when the runway could not be annotated
+  pub note: Option<String>,
+}
+
+/// Approaches and takeoffs share the same annotation schema
+pub type Approach = RunwaySegment;
+pub type Takeoff = RunwaySegment;
+
+/// Which phase of flight a segment covers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentKind {
+  #[default]
+  Approach,
+  Takeoff,
+}
+
+impl SegmentKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SegmentKind::Approach => "approach",
+      SegmentKind::Takeoff => "takeoff",
+    }
+  }
+}
+
+impl fmt::Display for SegmentKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FlightData {
+  pub approaches: Option<Vec<Approach>>,
+  pub takeoffs: Option<Vec<Takeoff>>,
+}
+
+impl FlightData {
+  /// All approaches followed by all takeoffs
+  pub fn runway_segments(&self) -> impl Iterator<Item = (SegmentKind, &RunwaySegment)> {
+    let approaches = self.approaches.iter().flatten()
+      .map(|approach| (SegmentKind::Approach, approach));
+    let takeoffs = self.takeoffs.iter().flatten()
+      .map(|takeoff| (SegmentKind::Takeoff, takeoff));
+    approaches.chain(takeoffs)
+  }
+}
+
+/// A range of frames in one video, ready for processing
+#[derive(Default, Debug, Clone)]
+pub struct SegmentDescriptor {
+  pub kind: SegmentKind,
+  pub timestamp_str: String,
+  pub stream: String,
+  pub file_path: PathBuf,
+  pub start_frame: u32,
+  pub end_frame: u32,
+  pub annotated_frame: u32,
+  pub validated_runway: bool,
+  pub icao: String,
+  pub runway_designator: String,
+}
+
+/// Errors that prevent a manifest from being used at all
+#[derive(Debug)]
+pub enum ManifestError {
+  Io(std::io::Error),
+  Parse(serde_json::Error),
+}
+
+impl fmt::Display for ManifestError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ManifestError::Io(e) => write!(f, "couldn't read manifest: {}", e),
+      ManifestError::Parse(e) => write!(f, "couldn't parse manifest: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for ManifestError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      ManifestError::Io(e) => Some(e),
+      ManifestError::Parse(e) => Some(e),
+    }
+  }
+}
+
+impl From<std::io::Error> for ManifestError {
+  fn from(e: std::io::Error) -> Self {
+    ManifestError::Io(e)
+  }
+}
+
+impl From<serde_json::Error> for ManifestError {
+  fn from(e: serde_json::Error) -> Self {
+    ManifestError::Parse(e)
+  }
+}
+
+/// Something wrong with a single manifest entry
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProblemKind {
+  /// start_frame is not before end_frame
+  EmptyRange { start_frame: u32, end_frame: u32 },
+  /// The annotated frame lies outside the segment's frame range
+  AnnotationOutsideRange { frame: u32 },
+  /// The bounding box is inverted or extends past the frame
+  BadBoundingBox { width: Option<u32>, height: Option<u32> },
+  /// The keypoints lie outside the frame
+  KeypointsOutsideFrame { width: u32, height: u32 },
+  /// Not a four-character ICAO airport code
+  BadIcao(String),
+  /// The referenced video file does not exist
+  MissingVideo(PathBuf),
+}
+
+impl ProblemKind {
+  /// Whether the segment's frames can't be processed at all, rather than just its annotations being off
+  pub fn prevents_processing(&self) -> bool {
+    matches!(self, ProblemKind::EmptyRange { .. } | ProblemKind::MissingVideo(_))
+  }
+}
+
+impl fmt::Display for ProblemKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ProblemKind::EmptyRange { start_frame, end_frame } =>
+        write!(f, "start frame {} is not before end frame {}", start_frame, end_frame),
+      ProblemKind::AnnotationOutsideRange { frame } =>
+        write!(f, "annotated frame {} is outside the segment", frame),
+      ProblemKind::BadBoundingBox { width: Some(width), height: Some(height) } =>
+        write!(f, "bounding box is inverted or outside the {}x{} frame", width, height),
+      ProblemKind::BadBoundingBox { .. } =>
+        write!(f, "bounding box is inverted"),
+      ProblemKind::KeypointsOutsideFrame { width, height } =>
+        write!(f, "keypoints are outside the {}x{} frame", width, height),
+      ProblemKind::BadIcao(icao) =>
+        write!(f, "'{}' is not an ICAO airport code", icao),
+      ProblemKind::MissingVideo(path) =>
+        write!(f, "video {:?} not found", path),
+    }
+  }
+}
+
+/// A problem with one segment of the manifest
+#[derive(Debug, Clone)]
+pub struct ManifestProblem {
+  pub segment_kind: SegmentKind,
+  pub timestamp_str: String,
+  pub stream: String,
+  pub start_frame: u32,
+  pub kind: ProblemKind,
+}
+
+impl ManifestProblem {
+  /// Does this problem concern the given segment?
+  pub fn affects(&self, segment: &SegmentDescriptor) -> bool {
+    self.segment_kind == segment.kind &&
+      self.timestamp_str == segment.timestamp_str &&
+      self.stream == segment.stream &&
+      self.start_frame == segment.start_frame
+  }
+}
+
+impl fmt::Display for ManifestProblem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} {} {} @{}: {}",
+           self.timestamp_str, self.segment_kind, self.stream, self.start_frame, self.kind)
+  }
+}
+
+/// All the problems found when validating a manifest
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+  pub problems: Vec<ManifestProblem>,
+}
+
+impl ValidationReport {
+  pub fn is_clean(&self) -> bool {
+    self.problems.is_empty()
+  }
+
+  /// The first problem that keeps the given segment from being processed, if any
+  pub fn blocking(&self, segment: &SegmentDescriptor) -> Option<&ManifestProblem> {
+    self.problems.iter().find(|problem| problem.kind.prevents_processing() && problem.affects(segment))
+  }
+}
+
+impl fmt::Display for ValidationReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    writeln!(f, "{} manifest problems", self.problems.len())?;
+    for problem in &self.problems {
+      writeln!(f, "  {}", problem)?;
+    }
+    Ok(())
+  }
+}
+
+/// The parsed contents of an approaches manifest
+#[derive(Debug)]
+pub struct Manifest {
+  /// Where the manifest was loaded from: videos are found alongside it
+  pub path: PathBuf,
+  /// Flight data keyed by flight timestamp
+  pub flights: BTreeMap<String, FlightData>,
+}
+
+impl Manifest {
+  pub fn load(manifest_path: &Path) -> Result<Self, ManifestError> {
+    let mut manifest_file = File::open(manifest_path)?;
+    let mut contents = String::new();
+    manifest_file.read_to_string(&mut contents)?;
+    let flights = serde_json::from_str(&contents)?;
+    Ok(Self { path: manifest_path.to_path_buf(), flights })
+  }
+
+  /// Path of the video file for a stream named in the manifest
+  pub fn video_path(&self, stream: &str) -> PathBuf {
+    self.path.with_file_name(stream.to_owned() + ".mp4")
+  }
+
+  /// All approach and takeoff segments, ordered by flight timestamp then manifest order
+  pub fn segments(&self) -> Vec<SegmentDescriptor> {
+    let mut segments: Vec<SegmentDescriptor> = Vec::new();
+    for (timestamp, flight_data) in &self.flights {
+      for (kind, entry) in flight_data.runway_segments() {
+        let mut desc = SegmentDescriptor {
+          kind,
+          timestamp_str: timestamp.clone(),
+          stream: entry.stream.clone(),
+          file_path: self.video_path(&entry.stream),
+          start_frame: entry.start_frame,
+          end_frame: entry.end_frame,
+          icao: entry.icao.clone(),
+          runway_designator: entry.runway_designator.clone(),
+          ..Default::default()
+        };
+        // a note means we failed to annotate the runway
+        if entry.note.is_none() {
+          if let Some(keypt) = &entry.annotated_keypoints {
+            desc.validated_runway = true;
+            desc.annotated_frame = keypt.frame;
+          }
+          else if let Some(bbox) = &entry.annotated_bbox {
+            desc.validated_runway = true;
+            desc.annotated_frame = bbox.frame;
+          }
+        }
+        segments.push(desc);
+      }
+    }
+    segments
+  }
+
+  /// Check every entry, collecting all problems rather than stopping at the first.
+  /// - `dimensions` provides the (width, height) of a video's frames, if known
+  pub fn validate<F>(&self, dimensions: F) -> ValidationReport
+    where F: Fn(&Path) -> Option<(u32, u32)>
+  {
+    let icao_regex = Regex::new(r"^[A-Z][A-Z0-9]{3}$").unwrap();
+    let mut report = ValidationReport::default();
+
+    for (timestamp, flight_data) in &self.flights {
+      for (segment_kind, entry) in flight_data.runway_segments() {
+        let mut report_problem = |kind: ProblemKind| {
+          report.problems.push(ManifestProblem {
+            segment_kind,
+            timestamp_str: timestamp.clone(),
+            stream: entry.stream.clone(),
+            start_frame: entry.start_frame,
+            kind,
+          });
+        };
+
+        if entry.start_frame >= entry.end_frame {
+          report_problem(ProblemKind::EmptyRange {
+            start_frame: entry.start_frame,
+            end_frame: entry.end_frame,
+          });
+        }
+        if !icao_regex.is_match(&entry.icao) {
+          report_problem(ProblemKind::BadIcao(entry.icao.clone()));
+        }
+
+        let video_path = self.video_path(&entry.stream);
+        let dims = if video_path.exists() {
+          dimensions(&video_path)
+        } else {
+          report_problem(ProblemKind::MissingVideo(video_path));
+          None
+        };
+
+        let in_range = |frame: u32| frame >= entry.start_frame && frame <= entry.end_frame;
+        if let Some(keypt) = &entry.annotated_keypoints {
+          if !in_range(keypt.frame) {
+            report_problem(ProblemKind::AnnotationOutsideRange { frame: keypt.frame });
+          }
+        }
+        if let Some(bbox) = &entry.annotated_bbox {
+          if !in_range(bbox.frame) {
+            report_problem(ProblemKind::AnnotationOutsideRange { frame: bbox.frame });
+          }
+          if bbox.tl_x >= bbox.br_x || bbox.tl_y >= bbox.br_y {
+            report_problem(ProblemKind::BadBoundingBox {
+              width: dims.map(|dims| dims.0),
+              height: dims.map(|dims| dims.1),
+            });
+          }
+        }
+        if let Some((width, height)) = dims {
+          for kind in frame_problems(entry, width, height) {
+            report_problem(kind);
+          }
+        }
+      }
+    }
+    report
+  }
+
+  /// Check a segment's annotations against the size of its video's frames,
+  /// for a `validate` run that didn't know the dimensions
+  pub fn frame_problems(&self, segment: &SegmentDescriptor, width: u32, height: u32) -> Vec<ManifestProblem> {
+    let Some(flight_data) = self.flights.get(&segment.timestamp_str) else {
+      return Vec::new();
+    };
+    flight_data.runway_segments()
+      .filter(|(kind, entry)| *kind == segment.kind && entry.stream == segment.stream
+                              && entry.start_frame == segment.start_frame)
+      .flat_map(|(segment_kind, entry)| frame_problems(entry, width, height).into_iter()
+        .map(move |kind| ManifestProblem {
+          segment_kind,
+          timestamp_str: segment.timestamp_str.clone(),
+          stream: entry.stream.clone(),
+          start_frame: entry.start_frame,
+          kind,
+        }))
+      .collect()
+  }
+}
+
+/// Annotations that lie outside frames of the given size.
+/// Inverted bounding boxes are reported regardless of the size, so aren't repeated here.
+fn frame_problems(entry: &RunwaySegment, width: u32, height: u32) -> Vec<ProblemKind> {
+  let mut problems = Vec::new();
+  if let Some(keypt) = &entry.annotated_keypoints {
+    if keypt.x1.max(keypt.x2) >= width || keypt.y1.max(keypt.y2) >= height {
+      problems.push(ProblemKind::KeypointsOutsideFrame { width, height });
+    }
+  }
+  if let Some(bbox) = &entry.annotated_bbox {
+    let inverted = bbox.tl_x >= bbox.br_x || bbox.tl_y >= bbox.br_y;
+    if !inverted && (bbox.br_x > width || bbox.br_y > height) {
+      problems.push(ProblemKind::BadBoundingBox { width: Some(width), height: Some(height) });
+    }
+  }
+  problems
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn entry(stream: &str, start_frame: u32, end_frame: u32, icao: &str) -> RunwaySegment {
+    RunwaySegment {
+      stream: stream.to_string(),
+      start_frame,
+      end_frame,
+      icao: icao.to_string(),
+      runway_designator: "28L".to_string(),
+      annotated_keypoints: None,
+      annotated_bbox: None,
+      note: None,
+    }
+  }
+
+  fn bbox(frame: u32, br_x: u32, br_y: u32) -> Option<BoundingBox> {
+    Some(BoundingBox { frame, tl_x: 10, tl_y: 10, br_x, br_y })
+  }
+
+  /// A manifest of one flight in `dir`, with a video file for every stream but "missing"
+  fn manifest(dir: &Path, approaches: Vec<RunwaySegment>, takeoffs: Vec<RunwaySegment>) -> Manifest {
+    for stream in approaches.iter().chain(&takeoffs).map(|entry| &entry.stream) {
+      if stream != "missing" {
+        File::create(dir.join(format!("{}.mp4", stream))).unwrap();
+      }
+    }
+    let flight = FlightData { approaches: Some(approaches), takeoffs: Some(takeoffs) };
+    Manifest {
+      path: dir.join("manifest.json"),
+      flights: BTreeMap::from([("2023-10-01T12:00:00".to_string(), flight)]),
+    }
+  }
+
+  fn kinds(report: &ValidationReport) -> Vec<&ProblemKind> {
+    report.problems.iter().map(|problem| &problem.kind).collect()
+  }
+
+  #[test]
+  fn empty_and_reversed_ranges() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = manifest(dir.path(), vec![entry("a", 100, 100, "KSFO"), entry("a", 200, 150, "KSFO")], vec![]);
+    assert_eq!(kinds(&manifest.validate(|_| None)), [
+      &ProblemKind::EmptyRange { start_frame: 100, end_frame: 100 },
+      &ProblemKind::EmptyRange { start_frame: 200, end_frame: 150 },
+    ]);
+  }
+
+  #[test]
+  fn malformed_icao_codes() {
+    let dir = tempfile::tempdir().unwrap();
+    let approaches = ["KSFO", "K1O4", "ksfo", "KSF", "KSFOX"].iter()
+      .map(|icao| entry("a", 0, 10, icao))
+      .collect();
+    let report = manifest(dir.path(), approaches, vec![]).validate(|_| None);
+    assert_eq!(kinds(&report), [
+      &ProblemKind::BadIcao("ksfo".to_string()),
+      &ProblemKind::BadIcao("KSF".to_string()),
+      &ProblemKind::BadIcao("KSFOX".to_string()),
+    ]);
+  }
+
+  #[test]
+  fn annotation_outside_the_segment() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut inside = entry("a", 100, 200, "KSFO");
+    inside.annotated_bbox = bbox(200, 50, 50);
+    let mut outside = entry("a", 300, 400, "KSFO");
+    outside.annotated_bbox = bbox(250, 50, 50);
+    let report = manifest(dir.path(), vec![inside, outside], vec![]).validate(|_| None);
+    assert_eq!(kinds(&report), [&ProblemKind::AnnotationOutsideRange { frame: 250 }]);
+    assert_eq!(report.problems[0].start_frame, 300);
+  }
+
+  #[test]
+  fn bounding_box_outside_the_frame() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut fits = entry("a", 0, 10, "KSFO");
+    fits.annotated_bbox = bbox(5, 640, 480);
+    let mut wide = entry("a", 20, 30, "KSFO");
+    wide.annotated_bbox = bbox(25, 641, 480);
+    let mut inverted = entry("a", 40, 50, "KSFO");
+    inverted.annotated_bbox = bbox(45, 5, 100);
+    let manifest = manifest(dir.path(), vec![fits, wide, inverted], vec![]);
+
+    let outside = ProblemKind::BadBoundingBox { width: Some(640), height: Some(480) };
+    let report = manifest.validate(|_| Some((640, 480)));
+    assert_eq!(kinds(&report), [&outside, &outside]);
+    assert_eq!(report.problems.iter().map(|problem| problem.start_frame).collect::<Vec<_>>(), [20, 40]);
+
+    // without the frame size only the inverted box is caught, and the rest once the video is opened
+    let report = manifest.validate(|_| None);
+    assert_eq!(kinds(&report), [&ProblemKind::BadBoundingBox { width: None, height: None }]);
+    let segments = manifest.segments();
+    assert!(manifest.frame_problems(&segments[0], 640, 480).is_empty());
+    let problems = manifest.frame_problems(&segments[1], 640, 480);
+    assert_eq!(problems.iter().map(|problem| &problem.kind).collect::<Vec<_>>(), [&outside]);
+  }
+
+  #[test]
+  fn takeoffs_are_validated_with_approaches() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = manifest(dir.path(), vec![entry("a", 0, 10, "KSFO")], vec![entry("b", 50, 60, "sfo")]);
+    let segments = manifest.segments();
+    assert_eq!(segments.iter().map(|seg| seg.kind).collect::<Vec<_>>(), [SegmentKind::Approach, SegmentKind::Takeoff]);
+
+    let report = manifest.validate(|_| None);
+    assert_eq!(kinds(&report), [&ProblemKind::BadIcao("sfo".to_string())]);
+    assert_eq!(report.problems[0].segment_kind, SegmentKind::Takeoff);
+    assert!(report.problems[0].affects(&segments[1]));
+    assert!(!report.problems[0].affects(&segments[0]));
+  }
+
+  #[test]
+  fn only_unprocessable_segments_are_blocked() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = manifest(dir.path(), vec![
+      entry("a", 0, 10, "KSFO"),
+      entry("a", 20, 30, "ksfo"),
+      entry("a", 40, 40, "KSFO"),
+      entry("missing", 0, 10, "KSFO"),
+    ], vec![]);
+    let report = manifest.validate(|_| None);
+    let segments = manifest.segments();
+    let blocking: Vec<Option<&ProblemKind>> = segments.iter()
+      .map(|seg| report.blocking(seg).map(|problem| &problem.kind))
+      .collect();
+    assert_eq!(blocking, [
+      None,
+      None,
+      Some(&ProblemKind::EmptyRange { start_frame: 40, end_frame: 40 }),
+      Some(&ProblemKind::MissingVideo(dir.path().join("missing.mp4"))),
+    ]);
+  }
+}
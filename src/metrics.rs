@@ -0,0 +1,321 @@
+//! Full-reference metrics: how an image differs from a reference image of the same size
+
+use std::fmt;
+use std::str::FromStr;
+
+use image::imageops::{resize, FilterType};
+use image::{GrayImage, ImageBuffer, Luma};
+use image_compare::{Algorithm, CompareError, Metric};
+use imageproc::filter::gaussian_blur_f32;
+use imageproc::gradients::{horizontal_prewitt, vertical_prewitt};
+use serde::{Deserialize, Serialize};
+
+use crate::parse_name;
+
+/// PSNR reported for identical images, whose PSNR would be infinite
+pub const MAX_PSNR: f64 = 100.0;
+
+/// Weights of the MS-SSIM scales, finest first (Wang, Simoncelli & Bovik, 2003)
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+/// Images are compared at coarser MS-SSIM scales only while both sides are at least this long
+const MS_SSIM_MIN_SIDE: u32 = 16;
+/// Standard deviation of the Gaussian window over which SSIM statistics are gathered
+const SSIM_SIGMA: f32 = 1.5;
+/// SSIM stabilizing constants, for intensities in [0, 1]
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C2: f64 = 0.03 * 0.03;
+/// GMSD stabilizing constant, for 8-bit intensities (Xue et al., 2014)
+const GMSD_C: f64 = 170.0;
+
+/// A single-channel image of intensities in [0, 1]
+type FloatImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// The metrics `compare_images_with` can record in an `ImgComparison`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ComparisonMetric {
+  /// Root mean squared difference of pixel values
+  Rmse,
+  /// Peak signal-to-noise ratio in dB, at most `MAX_PSNR`
+  Psnr,
+  /// Mean structural similarity over windows, as computed by `image_compare`
+  Ssim,
+  /// Structural similarity combined over five successively halved scales
+  MsSsim,
+  /// Gradient magnitude similarity deviation: 0 for identical images, larger is worse
+  Gmsd,
+  /// Correlation of the intensity histograms
+  HistCorrelation,
+  /// Hellinger distance between the intensity histograms, in [0, 1]
+  Hellinger,
+  /// Symmetric chi-square distance between the intensity histograms, in [0, 1]
+  ChiSquare,
+  /// Bhattacharyya distance between the intensity histograms
+  Bhattacharyya,
+}
+
+impl ComparisonMetric {
+  pub const ALL: [ComparisonMetric; 9] = [
+    ComparisonMetric::Rmse,
+    ComparisonMetric::Psnr,
+    ComparisonMetric::Ssim,
+    ComparisonMetric::MsSsim,
+    ComparisonMetric::Gmsd,
+    ComparisonMetric::HistCorrelation,
+    ComparisonMetric::Hellinger,
+    ComparisonMetric::ChiSquare,
+    ComparisonMetric::Bhattacharyya,
+  ];
+
+  /// The metrics compared unless the caller chooses others
+  pub const DEFAULT_SET: [ComparisonMetric; 2] = [
+    ComparisonMetric::Ssim,
+    ComparisonMetric::HistCorrelation,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      ComparisonMetric::Rmse => "rmse",
+      ComparisonMetric::Psnr => "psnr",
+      ComparisonMetric::Ssim => "ssim",
+      ComparisonMetric::MsSsim => "ms_ssim",
+      ComparisonMetric::Gmsd => "gmsd",
+      ComparisonMetric::HistCorrelation => "hist_correlation",
+      ComparisonMetric::Hellinger => "hellinger",
+      ComparisonMetric::ChiSquare => "chi_square",
+      ComparisonMetric::Bhattacharyya => "bhattacharyya",
+    }
+  }
+
+  /// Name of the `ImgComparison` field (and record column) holding this metric
+  pub fn column(&self) -> &'static str {
+    match self {
+      ComparisonMetric::Rmse => "rms_error",
+      ComparisonMetric::Psnr => "psnr",
+      ComparisonMetric::Ssim => "ssim_score",
+      ComparisonMetric::MsSsim => "ms_ssim_score",
+      ComparisonMetric::Gmsd => "gmsd",
+      ComparisonMetric::HistCorrelation => "hsim_score",
+      ComparisonMetric::Hellinger => "hellinger_distance",
+      ComparisonMetric::ChiSquare => "chi_square_distance",
+      ComparisonMetric::Bhattacharyya => "bhattacharyya_distance",
+    }
+  }
+
+  /// Measure how `image` differs from `reference`
+  pub fn measure(&self, reference: &GrayImage, image: &GrayImage) -> Result<f64, CompareError> {
+    if reference.dimensions() != image.dimensions() {
+      return Err(CompareError::DimensionsDiffer);
+    }
+    Ok(match self {
+      ComparisonMetric::Rmse => imageproc::stats::root_mean_squared_error(reference, image),
+      ComparisonMetric::Psnr => psnr(imageproc::stats::root_mean_squared_error(reference, image)),
+      ComparisonMetric::Ssim => {
+        image_compare::gray_similarity_structure(&Algorithm::MSSIMSimple, reference, image)?.score
+      }
+      ComparisonMetric::MsSsim => ms_ssim(reference, image),
+      ComparisonMetric::Gmsd => gmsd(reference, image),
+      ComparisonMetric::HistCorrelation => {
+        image_compare::gray_similarity_histogram(Metric::Correlation, reference, image)?
+      }
+      ComparisonMetric::Hellinger => {
+        let coefficient = bhattacharyya_coefficient(&normalized_histogram(reference), &normalized_histogram(image));
+        (1.0 - coefficient).max(0.0).sqrt()
+      }
+      ComparisonMetric::ChiSquare => chi_square(&normalized_histogram(reference), &normalized_histogram(image)),
+      ComparisonMetric::Bhattacharyya => {
+        let coefficient = bhattacharyya_coefficient(&normalized_histogram(reference), &normalized_histogram(image));
+        // histograms with no overlap are infinitely far apart
+        -coefficient.max(f64::MIN_POSITIVE).ln()
+      }
+    })
+  }
+}
+
+impl fmt::Display for ComparisonMetric {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+impl FromStr for ComparisonMetric {
+  type Err = String;
+
+  /// Parse a metric name, accepting `-` for `_`
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    parse_name(name, &ComparisonMetric::ALL, ComparisonMetric::name, "metric")
+  }
+}
+
+fn psnr(rms_error: f64) -> f64 {
+  if rms_error == 0.0 {
+    return MAX_PSNR;
+  }
+  (20.0 * (255.0 / rms_error).log10()).min(MAX_PSNR)
+}
+
+/// Intensity histogram, normalized to sum to 1
+fn normalized_histogram(image: &GrayImage) -> [f64; 256] {
+  let mut hist = [0.0; 256];
+  for pixel in image.pixels() {
+    hist[pixel[0] as usize] += 1.0;
+  }
+  let total = image.width() as f64 * image.height() as f64;
+  if total > 0.0 {
+    for bin in hist.iter_mut() {
+      *bin /= total;
+    }
+  }
+  hist
+}
+
+/// Overlap of two normalized histograms: 1 when identical, 0 when disjoint
+fn bhattacharyya_coefficient(first: &[f64; 256], second: &[f64; 256]) -> f64 {
+  first.iter().zip(second).map(|(p, q)| (p * q).sqrt()).sum()
+}
+
+/// Half the chi-square distance summed over the bins either histogram occupies
+fn chi_square(first: &[f64; 256], second: &[f64; 256]) -> f64 {
+  let sum: f64 = first.iter().zip(second)
+    .filter(|(p, q)| *p + *q > 0.0)
+    .map(|(p, q)| (p - q) * (p - q) / (p + q))
+    .sum();
+  0.5 * sum
+}
+
+fn to_float(image: &GrayImage) -> FloatImage {
+  ImageBuffer::from_fn(image.width(), image.height(), |x, y| Luma([image.get_pixel(x, y)[0] as f32 / 255.0]))
+}
+
+fn product(first: &FloatImage, second: &FloatImage) -> FloatImage {
+  ImageBuffer::from_fn(first.width(), first.height(), |x, y| {
+    Luma([first.get_pixel(x, y)[0] * second.get_pixel(x, y)[0]])
+  })
+}
+
+/// Mean SSIM luminance term and mean contrast-structure term, over Gaussian windows
+fn ssim_terms(first: &FloatImage, second: &FloatImage) -> (f64, f64) {
+  let mean_first = gaussian_blur_f32(first, SSIM_SIGMA);
+  let mean_second = gaussian_blur_f32(second, SSIM_SIGMA);
+  let mean_sq_first = gaussian_blur_f32(&product(first, first), SSIM_SIGMA);
+  let mean_sq_second = gaussian_blur_f32(&product(second, second), SSIM_SIGMA);
+  let mean_cross = gaussian_blur_f32(&product(first, second), SSIM_SIGMA);
+
+  let npixels = mean_first.as_raw().len();
+  if npixels == 0 {
+    return (1.0, 1.0);
+  }
+  let mut luminance = 0.0;
+  let mut contrast_structure = 0.0;
+  for idx in 0..npixels {
+    let mu1 = mean_first.as_raw()[idx] as f64;
+    let mu2 = mean_second.as_raw()[idx] as f64;
+    let var1 = (mean_sq_first.as_raw()[idx] as f64 - mu1 * mu1).max(0.0);
+    let var2 = (mean_sq_second.as_raw()[idx] as f64 - mu2 * mu2).max(0.0);
+    let covar = mean_cross.as_raw()[idx] as f64 - mu1 * mu2;
+    luminance += (2.0 * mu1 * mu2 + SSIM_C1) / (mu1 * mu1 + mu2 * mu2 + SSIM_C1);
+    contrast_structure += (2.0 * covar + SSIM_C2) / (var1 + var2 + SSIM_C2);
+  }
+  (luminance / npixels as f64, contrast_structure / npixels as f64)
+}
+
+fn half_size<I: image::GenericImageView>(image: &I)
+  -> ImageBuffer<I::Pixel, Vec<<I::Pixel as image::Pixel>::Subpixel>>
+  where I::Pixel: 'static
+{
+  resize(image, (image.width() / 2).max(1), (image.height() / 2).max(1), FilterType::Triangle)
+}
+
+/// Multi-scale SSIM. Scales too small to compare are dropped, and the remaining weights rescaled.
+fn ms_ssim(reference: &GrayImage, image: &GrayImage) -> f64 {
+  let min_side = reference.width().min(reference.height());
+  let nscales = (0..MS_SSIM_WEIGHTS.len())
+    .take_while(|&scale| min_side >> scale >= MS_SSIM_MIN_SIDE)
+    .count()
+    .max(1);
+  let weights = &MS_SSIM_WEIGHTS[..nscales];
+  let total_weight: f64 = weights.iter().sum();
+
+  let mut first = to_float(reference);
+  let mut second = to_float(image);
+  let mut score = 1.0;
+  for (scale, weight) in weights.iter().enumerate() {
+    let weight = weight / total_weight;
+    let (luminance, contrast_structure) = ssim_terms(&first, &second);
+    // a negative term has no fractional power: treat it as no similarity
+    score *= contrast_structure.max(0.0).powf(weight);
+    if scale + 1 == nscales {
+      score *= luminance.max(0.0).powf(weight);
+    } else {
+      first = half_size(&first);
+      second = half_size(&second);
+    }
+  }
+  score
+}
+
+/// Gradient magnitude similarity deviation, computed on the images halved in size
+fn gmsd(reference: &GrayImage, image: &GrayImage) -> f64 {
+  let magnitudes = |image: &GrayImage| -> Vec<f64> {
+    let image = half_size(image);
+    let horizontal = horizontal_prewitt(&image);
+    let vertical = vertical_prewitt(&image);
+    horizontal.pixels().zip(vertical.pixels())
+      .map(|(gx, gy)| {
+        // imageproc's Prewitt kernels are unnormalized
+        let (gx, gy) = (gx[0] as f64 / 3.0, gy[0] as f64 / 3.0);
+        (gx * gx + gy * gy).sqrt()
+      })
+      .collect()
+  };
+  let similarities: Vec<f64> = magnitudes(reference).iter().zip(magnitudes(image))
+    .map(|(m1, m2)| (2.0 * m1 * m2 + GMSD_C) / (m1 * m1 + m2 * m2 + GMSD_C))
+    .collect();
+  if similarities.is_empty() {
+    return 0.0;
+  }
+  let count = similarities.len() as f64;
+  let mean = similarities.iter().sum::<f64>() / count;
+  let variance = similarities.iter().map(|sim| (sim - mean) * (sim - mean)).sum::<f64>() / count;
+  variance.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn textured(width: u32, height: u32) -> GrayImage {
+    GrayImage::from_fn(width, height, |x, y| Luma([((x * x + 3 * y * y + x * y) % 251) as u8]))
+  }
+
+  fn measure(metric: ComparisonMetric, reference: &GrayImage, image: &GrayImage) -> f64 {
+    metric.measure(reference, image).unwrap()
+  }
+
+  #[test]
+  fn identical_images_match_perfectly() {
+    let image = textured(128, 96);
+    assert!((measure(ComparisonMetric::MsSsim, &image, &image) - 1.0).abs() < 1e-6);
+    assert!(measure(ComparisonMetric::Gmsd, &image, &image).abs() < 1e-9);
+    assert!(measure(ComparisonMetric::Hellinger, &image, &image).abs() < 1e-6);
+    assert!(measure(ComparisonMetric::ChiSquare, &image, &image).abs() < 1e-12);
+  }
+
+  #[test]
+  fn different_images_score_worse() {
+    let reference = textured(128, 96);
+    let inverted = GrayImage::from_fn(128, 96, |x, y| Luma([255 - reference.get_pixel(x, y)[0]]));
+    assert!(measure(ComparisonMetric::MsSsim, &reference, &inverted) < 0.5);
+    // inverting keeps the gradient magnitudes, so GMSD needs the gradients changed
+    let blurred = imageproc::filter::gaussian_blur_f32(&reference, 2.0);
+    assert!(measure(ComparisonMetric::Gmsd, &reference, &blurred) > 0.05);
+  }
+
+  #[test]
+  fn disjoint_histograms_are_furthest_apart() {
+    let dark = GrayImage::from_pixel(32, 32, Luma([10]));
+    let bright = GrayImage::from_pixel(32, 32, Luma([200]));
+    assert!((measure(ComparisonMetric::Hellinger, &dark, &bright) - 1.0).abs() < 1e-9);
+    assert!((measure(ComparisonMetric::ChiSquare, &dark, &bright) - 1.0).abs() < 1e-9);
+  }
+}
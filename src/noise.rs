@@ -0,0 +1,62 @@
+//! Estimating sensor noise from a single image
+
+use image::GrayImage;
+
+use crate::quality::MAD_TO_STDDEV;
+
+/// L2 norm of Immerkær's mask, by which it scales the noise
+const MASK_NORM: f64 = 6.0;
+
+/// Standard deviation of the noise, in gray levels.
+///
+/// Filters the image with Immerkær's mask, the difference of two Laplacians,
+/// which cancels smooth intensity gradients and leaves mostly noise.
+/// Taking the median absolute residual, rather than his mean, keeps the edges that survive the mask
+/// from inflating the estimate. Noise much below a gray level is lost to quantization and reads as zero.
+pub fn noise_sigma(img: &GrayImage) -> f64 {
+  let (width, height) = (img.width() as usize, img.height() as usize);
+  if width < 3 || height < 3 {
+    return 0.0;
+  }
+  let pixels = img.as_raw();
+  let at = |x: usize, y: usize| pixels[y * width + x] as i32;
+
+  // residuals are integers up to 16 * 255, so find their median by counting
+  let mut counts = vec![0u32; 16 * 255 + 1];
+  for y in 1..height - 1 {
+    for x in 1..width - 1 {
+      let corners = at(x - 1, y - 1) + at(x + 1, y - 1) + at(x - 1, y + 1) + at(x + 1, y + 1);
+      let edges = at(x, y - 1) + at(x - 1, y) + at(x + 1, y) + at(x, y + 1);
+      let residual = corners - 2 * edges + 4 * at(x, y);
+      counts[residual.unsigned_abs() as usize] += 1;
+    }
+  }
+
+  let half = ((width - 2) * (height - 2)) as u64 / 2;
+  let mut cumulative: u64 = 0;
+  let median = counts.iter()
+    .position(|count| {
+      cumulative += *count as u64;
+      cumulative > half
+    })
+    .unwrap_or(0);
+  MAD_TO_STDDEV * median as f64 / MASK_NORM
+}
+
+#[cfg(test)]
+mod tests {
+  use imageproc::noise::gaussian_noise;
+
+  use super::*;
+
+  #[test]
+  fn recovers_added_gaussian_noise() {
+    let flat = GrayImage::from_pixel(256, 256, image::Luma([128]));
+    assert_eq!(noise_sigma(&flat), 0.0);
+    for sigma in [2.0, 5.0, 10.0] {
+      let noisy = gaussian_noise(&flat, 0.0, sigma, 7);
+      let estimate = noise_sigma(&noisy);
+      assert!((estimate - sigma).abs() < 0.1 * sigma, "estimated {} for sigma {}", estimate, sigma);
+    }
+  }
+}
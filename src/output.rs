@@ -0,0 +1,133 @@
+//! Where tools write their outputs, and the record of what each run produced
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// File name of the run manifest written into each segment directory
+pub const RUN_MANIFEST_NAME: &str = "run.json";
+
+/// Lays out outputs under a root directory as `<root>/<video_id>/<segment>/<frame files>`
+#[derive(Debug, Clone)]
+pub struct OutputLayout {
+  pub root: PathBuf,
+}
+
+impl OutputLayout {
+  pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+    Self { root: root.into() }
+  }
+
+  pub fn segment_dir(&self, video_id: &str, segment: &str) -> PathBuf {
+    self.root.join(video_id).join(segment)
+  }
+
+  /// Create the directory for one segment's outputs.
+  /// A directory holding a previous run is an `AlreadyExists` error,
+  /// unless `overwrite` is set, in which case the previous run is removed first.
+  pub fn create_segment_dir(&self, video_id: &str, segment: &str, overwrite: bool) -> io::Result<PathBuf> {
+    let dir = self.segment_dir(video_id, segment);
+    if is_nonempty_dir(&dir)? {
+      if !overwrite {
+        return Err(io::Error::new(ErrorKind::AlreadyExists,
+                                  format!("{:?} holds a previous run", dir)));
+      }
+      fs::remove_dir_all(&dir)?;
+    }
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+  }
+}
+
+fn is_nonempty_dir(dir: &Path) -> io::Result<bool> {
+  match fs::read_dir(dir) {
+    Ok(mut entries) => Ok(entries.next().is_some()),
+    Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+    Err(e) => Err(e),
+  }
+}
+
+/// Create a new output file, refusing to replace an existing one unless `overwrite` is set
+pub fn create_output_file(path: &Path, overwrite: bool) -> io::Result<File> {
+  if overwrite {
+    File::create(path)
+  } else {
+    File::options().write(true).create_new(true).open(path)
+  }
+}
+
+/// The files written for one frame, relative to the segment directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FrameOutput {
+  pub index: usize,
+  pub pts: i64,
+  pub files: Vec<String>,
+}
+
+/// Describes what one run of a tool produced for one video segment
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunManifest {
+  /// The tool (subcommand) that produced the outputs
+  pub tool: String,
+  pub version: String,
+  /// RFC 3339 local times
+  pub started: String,
+  pub finished: Option<String>,
+  pub source: PathBuf,
+  pub video_id: String,
+  pub segment: String,
+  pub start_frame: usize,
+  /// None when the run continued to the end of the video
+  pub end_frame: Option<usize>,
+  /// Options that affect the outputs, such as crop fraction and image format
+  pub settings: BTreeMap<String, serde_json::Value>,
+  pub frames: Vec<FrameOutput>,
+  /// File of per-frame records, relative to the segment directory
+  pub records: Option<String>,
+  /// Frame errors that decoding skipped over
+  #[serde(default)]
+  pub skipped: Vec<String>,
+}
+
+impl RunManifest {
+  pub fn new(tool: &str, source: &Path, video_id: &str, segment: &str,
+             start_frame: usize, end_frame: Option<usize>) -> Self {
+    Self {
+      tool: tool.to_string(),
+      version: env!("CARGO_PKG_VERSION").to_string(),
+      started: chrono::Local::now().to_rfc3339(),
+      finished: None,
+      source: source.to_path_buf(),
+      video_id: video_id.to_string(),
+      segment: segment.to_string(),
+      start_frame,
+      end_frame,
+      settings: BTreeMap::new(),
+      frames: Vec::new(),
+      records: None,
+      skipped: Vec::new(),
+    }
+  }
+
+  pub fn setting<V: Serialize>(mut self, name: &str, value: V) -> Self {
+    let value = serde_json::to_value(value).unwrap_or(serde_json::Value::Null);
+    self.settings.insert(name.to_string(), value);
+    self
+  }
+
+  pub fn load(segment_dir: &Path) -> io::Result<Self> {
+    let file = File::open(segment_dir.join(RUN_MANIFEST_NAME))?;
+    Ok(serde_json::from_reader(io::BufReader::new(file))?)
+  }
+
+  /// Mark the run finished and write the manifest into the segment directory
+  pub fn finish(&mut self, segment_dir: &Path) -> io::Result<()> {
+    self.finished = Some(chrono::Local::now().to_rfc3339());
+    let file = File::create(segment_dir.join(RUN_MANIFEST_NAME))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+    Ok(())
+  }
+}
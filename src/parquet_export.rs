@@ -0,0 +1,125 @@
+//! Writing record tables as Apache Parquet
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use arrow_array::builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::Value;
+
+use crate::records::{Column, ColumnKind, RecordWriter, Row};
+
+/// Rows accumulated in the column builders before they're handed to the Parquet writer
+const BATCH_ROWS: usize = 8192;
+
+/// Accumulates the values of one column
+enum ColumnBuilder {
+  Int(Int64Builder),
+  Float(Float64Builder),
+  Bool(BooleanBuilder),
+  Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+  fn new(kind: ColumnKind) -> Self {
+    match kind {
+      ColumnKind::Int => ColumnBuilder::Int(Int64Builder::new()),
+      ColumnKind::Float => ColumnBuilder::Float(Float64Builder::new()),
+      ColumnKind::Bool => ColumnBuilder::Bool(BooleanBuilder::new()),
+      ColumnKind::Text => ColumnBuilder::Text(StringBuilder::new()),
+    }
+  }
+
+  /// Append a value, or null if it's missing or of the wrong type
+  fn append(&mut self, value: Option<&Value>) {
+    match self {
+      ColumnBuilder::Int(builder) => builder.append_option(value.and_then(Value::as_i64)),
+      ColumnBuilder::Float(builder) => builder.append_option(value.and_then(Value::as_f64)),
+      ColumnBuilder::Bool(builder) => builder.append_option(value.and_then(Value::as_bool)),
+      ColumnBuilder::Text(builder) => builder.append_option(value.and_then(Value::as_str)),
+    }
+  }
+
+  fn finish(&mut self) -> ArrayRef {
+    match self {
+      ColumnBuilder::Int(builder) => Arc::new(builder.finish()),
+      ColumnBuilder::Float(builder) => Arc::new(builder.finish()),
+      ColumnBuilder::Bool(builder) => Arc::new(builder.finish()),
+      ColumnBuilder::Text(builder) => Arc::new(builder.finish()),
+    }
+  }
+}
+
+fn data_type(kind: ColumnKind) -> DataType {
+  match kind {
+    ColumnKind::Int => DataType::Int64,
+    ColumnKind::Float => DataType::Float64,
+    ColumnKind::Bool => DataType::Boolean,
+    ColumnKind::Text => DataType::Utf8,
+  }
+}
+
+/// Writes rows to a single Parquet file, converting them to Arrow batches of up to `BATCH_ROWS` rows
+pub struct ParquetWriter<W: Write + Send> {
+  columns: Vec<Column>,
+  schema: SchemaRef,
+  builders: Vec<ColumnBuilder>,
+  buffered: usize,
+  /// None once finished
+  writer: Option<ArrowWriter<W>>,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+  pub fn new(columns: Vec<Column>, out: W) -> io::Result<Self> {
+    let fields: Vec<Field> = columns.iter()
+      .map(|col| Field::new(col.name.as_str(), data_type(col.kind), true))
+      .collect();
+    let schema: SchemaRef = Arc::new(Schema::new(fields));
+    let props = WriterProperties::builder()
+      .set_compression(Compression::SNAPPY)
+      .build();
+    let writer = ArrowWriter::try_new(out, schema.clone(), Some(props)).map_err(io::Error::other)?;
+    let builders = columns.iter().map(|col| ColumnBuilder::new(col.kind)).collect();
+    Ok(Self { columns, schema, builders, buffered: 0, writer: Some(writer) })
+  }
+
+  fn write_batch(&mut self) -> io::Result<()> {
+    if self.buffered == 0 {
+      return Ok(());
+    }
+    let arrays: Vec<ArrayRef> = self.builders.iter_mut().map(|builder| builder.finish()).collect();
+    let batch = RecordBatch::try_new(self.schema.clone(), arrays).map_err(io::Error::other)?;
+    self.buffered = 0;
+    match self.writer.as_mut() {
+      Some(writer) => writer.write(&batch).map_err(io::Error::other),
+      None => Err(io::Error::other("parquet writer already finished")),
+    }
+  }
+}
+
+impl<W: Write + Send> RecordWriter for ParquetWriter<W> {
+  fn write_row(&mut self, row: &Row) -> io::Result<()> {
+    for (col, builder) in self.columns.iter().zip(self.builders.iter_mut()) {
+      builder.append(row.get(&col.name));
+    }
+    self.buffered += 1;
+    if self.buffered >= BATCH_ROWS {
+      self.write_batch()?;
+    }
+    Ok(())
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    self.write_batch()?;
+    if let Some(writer) = self.writer.take() {
+      // closing writes the file footer
+      let mut out = writer.into_inner().map_err(io::Error::other)?;
+      out.flush()?;
+    }
+    Ok(())
+  }
+}
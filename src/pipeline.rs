@@ -0,0 +1,101 @@
+//! Fan work out across threads while keeping results in their original order
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Mutex;
+use std::thread;
+
+/// Run a three-stage pipeline:
+/// - `produce` runs on its own thread, handing jobs to the `send` callback it is given.
+///   `send` blocks while `depth` jobs are already queued, and returns false once
+///   the pipeline has shut down (so the producer should stop).
+/// - `analyze` runs on `workers` threads, turning each job into a result
+/// - `consume` runs on the calling thread, receiving results in the order the jobs were sent
+pub fn ordered_pipeline<J, R, P, A, C>(depth: usize, workers: usize, produce: P, analyze: A, mut consume: C)
+  where
+    J: Send,
+    R: Send,
+    P: FnOnce(&mut dyn FnMut(J) -> bool) + Send,
+    A: Fn(J) -> R + Sync,
+    C: FnMut(R),
+{
+  let (job_tx, job_rx) = sync_channel::<(u64, J)>(depth.max(1));
+  let (result_tx, result_rx) = sync_channel::<(u64, R)>(depth.max(1));
+  let job_rx: Mutex<Receiver<(u64, J)>> = Mutex::new(job_rx);
+
+  thread::scope(|scope| {
+    scope.spawn(move || {
+      let mut seq: u64 = 0;
+      let mut send = |job: J| {
+        let sent = job_tx.send((seq, job)).is_ok();
+        seq += 1;
+        sent
+      };
+      produce(&mut send);
+      // dropping job_tx here lets the workers drain the queue and exit
+    });
+
+    for _ in 0..workers.max(1) {
+      let result_tx = result_tx.clone();
+      let job_rx = &job_rx;
+      let analyze = &analyze;
+      scope.spawn(move || {
+        loop {
+          // hold the lock only while taking a job, not while analyzing it
+          let next = job_rx.lock().unwrap().recv();
+          match next {
+            Ok((seq, job)) => {
+              if result_tx.send((seq, analyze(job))).is_err() {
+                break;
+              }
+            }
+            Err(_) => break,
+          }
+        }
+      });
+    }
+    // only the workers' clones remain, so the loop below ends when they finish
+    drop(result_tx);
+
+    // results can arrive out of order: hold them until their turn comes
+    let mut pending: BTreeMap<u64, R> = BTreeMap::new();
+    let mut next_seq: u64 = 0;
+    for (seq, result) in result_rx {
+      pending.insert(seq, result);
+      while let Some(result) = pending.remove(&next_seq) {
+        consume(result);
+        next_seq += 1;
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+
+  #[test]
+  fn results_are_consumed_in_send_order() {
+    let mut consumed = Vec::new();
+    ordered_pipeline(
+      8,
+      4,
+      |send| {
+        for job in 0..64u64 {
+          if !send(job) {
+            break;
+          }
+        }
+      },
+      // jobs take varying times, so the workers finish them out of order
+      |job| {
+        thread::sleep(Duration::from_millis((64 - job) % 7));
+        job * 10
+      },
+      |result| consumed.push(result),
+    );
+    assert_eq!(consumed, (0..64).map(|job| job * 10).collect::<Vec<_>>());
+  }
+}
@@ -0,0 +1,372 @@
+//! Frame preprocessing as a pipeline of stages described in data (JSON or TOML),
+//! so every tool prepares frames the same way, and outputs can record exactly how.
+//!
+//! A pipeline config lists its stages in order, eg in TOML:
+//!
+//! ```toml
+//! [[stages]]
+//! stage = "channel_mix"
+//! red = 0.25
+//! green = 0.75
+//! blue = 0.0
+//!
+//! [[stages]]
+//! stage = "crop"
+//! fraction = 0.8
+//!
+//! [[stages]]
+//! stage = "clahe"
+//! tiles = 8
+//! clip_limit = 2.0
+//! ```
+
+use std::fmt;
+use std::io::{BufWriter, Write};
+use std::ops::Deref;
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::{GrayImage, ImageBuffer, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use crate::crop_gray_to_percent;
+use crate::vignetting::VignettingModel;
+
+/// Fraction of each dimension kept by the default pipeline: our lenses vignette heavily
+pub const DEFAULT_CROP: f32 = 0.8;
+
+/// Red, green and blue weights of the luma our cameras' gray frames have always been mixed to
+const RED_GREEN_WEIGHTS: [f32; 3] = [0.25, 0.75, 0.0];
+
+fn positive(val: f32) -> bool {
+  val > 0.0
+}
+
+/// One step of a `Pipeline`. Every stage but `ChannelMix` works on gray images.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum Stage {
+  /// Weigh the red, green and blue channels into gray. Only allowed first;
+  /// without it, frames are mixed as `Stage::RED_GREEN`.
+  ChannelMix { red: f32, green: f32, blue: f32 },
+  /// Flatten the lens vignetting. Must come before any crop or resize,
+  /// as the model's radius is measured across the full frame.
+  Devignette(VignettingModel),
+  /// Keep the center `fraction` of each dimension
+  Crop { fraction: f32 },
+  GaussianBlur { sigma: f32 },
+  /// Edge-preserving smoothing
+  Bilateral { window_size: u32, sigma_color: f32, sigma_spatial: f32 },
+  /// Map intensities `lower..=upper` linearly onto the full range, saturating the rest
+  StretchContrast { lower: u8, upper: u8 },
+  EqualizeHistogram,
+  /// Contrast-limited adaptive histogram equalization, over a `tiles` x `tiles` grid.
+  /// Each tile's histogram is clipped at `clip_limit` times its mean bin.
+  Clahe { tiles: u32, clip_limit: f32 },
+  /// Scale both dimensions by `scale`
+  Resize { scale: f32 },
+}
+
+impl Stage {
+  /// The red-green luma of `red_green_as_grey`
+  pub const RED_GREEN: Stage = Stage::ChannelMix {
+    red: RED_GREEN_WEIGHTS[0],
+    green: RED_GREEN_WEIGHTS[1],
+    blue: RED_GREEN_WEIGHTS[2],
+  };
+
+  /// Why the stage's parameters can't be used, if they can't
+  fn problem(&self) -> Option<String> {
+    match self {
+      Stage::ChannelMix { red, green, blue } if [red, green, blue].into_iter().any(|weight| !(0.0..).contains(weight)) =>
+        Some("channel weights can't be negative".to_string()),
+      Stage::Crop { fraction } if !positive(*fraction) || *fraction > 1.0 =>
+        Some(format!("fraction must be in (0, 1], got {}", fraction)),
+      Stage::GaussianBlur { sigma } if !positive(*sigma) =>
+        Some(format!("sigma must be positive, got {}", sigma)),
+      Stage::Bilateral { window_size, sigma_color, sigma_spatial }
+        if *window_size == 0 || !positive(*sigma_color) || !positive(*sigma_spatial) =>
+        Some("window size and sigmas must be positive".to_string()),
+      Stage::StretchContrast { lower, upper } if lower >= upper =>
+        Some(format!("lower ({}) must be below upper ({})", lower, upper)),
+      Stage::Clahe { tiles, clip_limit } if *tiles == 0 || !(1.0..).contains(clip_limit) =>
+        Some(format!("need at least one tile and a clip limit of at least 1, got {} and {}", tiles, clip_limit)),
+      Stage::Resize { scale } if !positive(*scale) =>
+        Some(format!("scale must be positive, got {}", scale)),
+      _ => None,
+    }
+  }
+
+  fn apply(&self, img: GrayImage) -> GrayImage {
+    match self {
+      // only ever first, where `Pipeline::apply` mixes the channels
+      Stage::ChannelMix { .. } => img,
+      Stage::Devignette(vignetting) => vignetting.devignette(&img),
+      Stage::Crop { fraction } => crop_gray_to_percent(&img, *fraction),
+      Stage::GaussianBlur { sigma } => imageproc::filter::gaussian_blur_f32(&img, *sigma),
+      Stage::Bilateral { window_size, sigma_color, sigma_spatial } =>
+        imageproc::filter::bilateral_filter(&img, *window_size, *sigma_color, *sigma_spatial),
+      Stage::StretchContrast { lower, upper } => imageproc::contrast::stretch_contrast(&img, *lower, *upper),
+      Stage::EqualizeHistogram => imageproc::contrast::equalize_histogram(&img),
+      Stage::Clahe { tiles, clip_limit } => clahe(&img, *tiles, *clip_limit),
+      Stage::Resize { scale } => {
+        let width = ((img.width() as f32 * scale).round() as u32).max(1);
+        let height = ((img.height() as f32 * scale).round() as u32).max(1);
+        imageops::resize(&img, width, height, FilterType::Triangle)
+      }
+    }
+  }
+}
+
+/// Why a pipeline config couldn't be loaded
+#[derive(Debug)]
+pub enum PipelineError {
+  Io(std::io::Error),
+  Json(serde_json::Error),
+  Toml(toml::de::Error),
+  /// Stage number (from 1), and what's wrong with it
+  Invalid { stage: usize, problem: String },
+}
+
+impl fmt::Display for PipelineError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      PipelineError::Io(e) => write!(f, "couldn't read pipeline: {}", e),
+      PipelineError::Json(e) => write!(f, "couldn't parse pipeline: {}", e),
+      PipelineError::Toml(e) => write!(f, "couldn't parse pipeline: {}", e),
+      PipelineError::Invalid { stage, problem } => write!(f, "pipeline stage {}: {}", stage, problem),
+    }
+  }
+}
+
+impl std::error::Error for PipelineError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      PipelineError::Io(e) => Some(e),
+      PipelineError::Json(e) => Some(e),
+      PipelineError::Toml(e) => Some(e),
+      PipelineError::Invalid { .. } => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for PipelineError {
+  fn from(e: std::io::Error) -> Self {
+    PipelineError::Io(e)
+  }
+}
+
+impl From<serde_json::Error> for PipelineError {
+  fn from(e: serde_json::Error) -> Self {
+    PipelineError::Json(e)
+  }
+}
+
+impl From<toml::de::Error> for PipelineError {
+  fn from(e: toml::de::Error) -> Self {
+    PipelineError::Toml(e)
+  }
+}
+
+/// The stages that turn a decoded RGB frame into the gray image that's analyzed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pipeline {
+  pub stages: Vec<Stage>,
+}
+
+impl Default for Pipeline {
+  /// Red-green luma, cropped to `DEFAULT_CROP`
+  fn default() -> Self {
+    Self::cropped(DEFAULT_CROP, None)
+  }
+}
+
+impl Pipeline {
+  /// No stages: frames are only mixed to gray
+  pub fn new() -> Self {
+    Self { stages: Vec::new() }
+  }
+
+  /// Red-green luma, devignetted if there's a model, then cropped to `fraction`
+  pub fn cropped(fraction: f32, vignetting: Option<VignettingModel>) -> Self {
+    let pipeline = Self::new().with(Stage::RED_GREEN);
+    let pipeline = match vignetting {
+      Some(vignetting) => pipeline.with(Stage::Devignette(vignetting)),
+      None => pipeline,
+    };
+    pipeline.with(Stage::Crop { fraction })
+  }
+
+  /// Add `stage` to the end
+  pub fn with(mut self, stage: Stage) -> Self {
+    self.stages.push(stage);
+    self
+  }
+
+  /// Load a pipeline config: TOML if the file name ends `.toml`, otherwise JSON
+  pub fn load(path: &Path) -> Result<Self, PipelineError> {
+    let text = std::fs::read_to_string(path)?;
+    let pipeline: Self = if path.extension().is_some_and(|ext| ext == "toml") {
+      toml::from_str(&text)?
+    } else {
+      serde_json::from_str(&text)?
+    };
+    pipeline.validate()?;
+    Ok(pipeline)
+  }
+
+  /// Check each stage's parameters, that channel mixing comes first,
+  /// and that devignetting comes before the frame is cropped or resized
+  pub fn validate(&self) -> Result<(), PipelineError> {
+    let mut reframed = false;
+    for (index, stage) in self.stages.iter().enumerate() {
+      let problem = match stage {
+        Stage::ChannelMix { .. } if index > 0 => Some("channel_mix must be the first stage".to_string()),
+        Stage::Devignette(_) if reframed => Some("devignette must come before any crop or resize".to_string()),
+        _ => stage.problem(),
+      };
+      if let Some(problem) = problem {
+        return Err(PipelineError::Invalid { stage: index + 1, problem });
+      }
+      reframed |= matches!(stage, Stage::Crop { .. } | Stage::Resize { .. });
+    }
+    Ok(())
+  }
+
+  /// Write as JSON, which `load` reads back
+  pub fn write<W: Write>(&self, out: W) -> std::io::Result<()> {
+    let mut out = BufWriter::new(out);
+    serde_json::to_writer_pretty(&mut out, self)?;
+    out.flush()
+  }
+
+  /// The channel weights, and the stages after mixing
+  fn mix(&self) -> ([f32; 3], &[Stage]) {
+    match self.stages.split_first() {
+      Some((Stage::ChannelMix { red, green, blue }, rest)) => ([*red, *green, *blue], rest),
+      _ => (RED_GREEN_WEIGHTS, &self.stages),
+    }
+  }
+
+  /// Mix a frame to gray, without running it through the remaining stages
+  pub fn mix_to_gray<C>(&self, input: &ImageBuffer<Rgb<u8>, C>) -> GrayImage
+    where C: Deref<Target = [u8]>
+  {
+    let ([red, green, blue], _) = self.mix();
+    let mut gray = GrayImage::new(input.width(), input.height());
+    for (out_pixel, in_pixel) in gray.pixels_mut().zip(input.pixels()) {
+      let [r, g, b] = in_pixel.0;
+      // truncating, as `red_green_as_grey` does
+      out_pixel.0[0] = (r as f32 * red + g as f32 * green + b as f32 * blue).min(u8::MAX as f32) as u8;
+    }
+    gray
+  }
+
+  /// Mix a frame to gray and run it through the remaining stages
+  pub fn apply<C>(&self, input: &ImageBuffer<Rgb<u8>, C>) -> GrayImage
+    where C: Deref<Target = [u8]>
+  {
+    let (_, stages) = self.mix();
+    stages.iter().fold(self.mix_to_gray(input), |img, stage| stage.apply(img))
+  }
+
+  /// Run a color frame through the stages after mixing, each channel separately
+  pub fn apply_rgb<C>(&self, input: &ImageBuffer<Rgb<u8>, C>) -> RgbImage
+    where C: Deref<Target = [u8]>
+  {
+    let (_, stages) = self.mix();
+    let channels: Vec<GrayImage> = (0..3)
+      .map(|channel| {
+        let plane = crate::mono_as_grey(input, channel);
+        stages.iter().fold(plane, |img, stage| stage.apply(img))
+      })
+      .collect();
+    RgbImage::from_fn(channels[0].width(), channels[0].height(), |x, y| {
+      Rgb([0, 1, 2].map(|channel| channels[channel].get_pixel(x, y).0[0]))
+    })
+  }
+}
+
+/// Intensity mapping that equalizes one tile's clipped histogram
+fn clahe_mapping(img: &GrayImage, left: u32, top: u32, right: u32, bottom: u32, clip_limit: f32) -> [u8; 256] {
+  let mut hist = [0u32; 256];
+  for y in top..bottom {
+    for x in left..right {
+      hist[img.get_pixel(x, y).0[0] as usize] += 1;
+    }
+  }
+  let total = ((right - left) * (bottom - top)).max(1);
+  let limit = ((clip_limit * total as f32 / 256.0) as u32).max(1);
+  let mut excess = 0;
+  for count in hist.iter_mut() {
+    excess += count.saturating_sub(limit);
+    *count = (*count).min(limit);
+  }
+  // spread what was clipped evenly over every intensity
+  for (intensity, count) in hist.iter_mut().enumerate() {
+    *count += excess / 256 + u32::from((intensity as u32) < excess % 256);
+  }
+
+  let mut mapping = [0u8; 256];
+  let mut cumulative = 0;
+  for (intensity, count) in hist.iter().enumerate() {
+    cumulative += count;
+    mapping[intensity] = (255.0 * cumulative as f32 / total as f32).round().min(255.0) as u8;
+  }
+  mapping
+}
+
+/// Contrast-limited adaptive histogram equalization: each pixel is mapped by the equalizations
+/// of the four nearest tiles, interpolated bilinearly between the tile centers
+fn clahe(img: &GrayImage, tiles: u32, clip_limit: f32) -> GrayImage {
+  let (width, height) = img.dimensions();
+  let tiles_x = tiles.min(width).max(1);
+  let tiles_y = tiles.min(height).max(1);
+  let mut mappings = Vec::with_capacity((tiles_x * tiles_y) as usize);
+  for ty in 0..tiles_y {
+    for tx in 0..tiles_x {
+      mappings.push(clahe_mapping(img,
+                                  tx * width / tiles_x, ty * height / tiles_y,
+                                  (tx + 1) * width / tiles_x, (ty + 1) * height / tiles_y,
+                                  clip_limit));
+    }
+  }
+
+  // the tile to each side of a coordinate, and how far towards the second it is
+  let neighbours = |pos: u32, size: u32, tiles: u32| {
+    let tile_pos = ((pos as f32 + 0.5) * tiles as f32 / size as f32 - 0.5).clamp(0.0, (tiles - 1) as f32);
+    let first = tile_pos.floor() as u32;
+    (first, (first + 1).min(tiles - 1), tile_pos - first as f32)
+  };
+  GrayImage::from_fn(width, height, |x, y| {
+    let (x0, x1, fx) = neighbours(x, width, tiles_x);
+    let (y0, y1, fy) = neighbours(y, height, tiles_y);
+    let val = img.get_pixel(x, y).0[0] as usize;
+    let at = |tx: u32, ty: u32| mappings[(ty * tiles_x + tx) as usize][val] as f32;
+    let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+    let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+    image::Luma([(top * (1.0 - fy) + bottom * fy).round() as u8])
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn devignetting_must_precede_cropping() {
+    let devignette = Stage::Devignette(VignettingModel::default());
+    assert!(Pipeline::cropped(0.9, Some(VignettingModel::default())).validate().is_ok());
+    for reframe in [Stage::Crop { fraction: 0.9 }, Stage::Resize { scale: 0.5 }] {
+      let pipeline = Pipeline::new().with(reframe).with(devignette.clone());
+      assert!(matches!(pipeline.validate(), Err(PipelineError::Invalid { stage: 2, .. })));
+    }
+  }
+
+  #[test]
+  fn default_pipeline_matches_red_green_crop() {
+    let rgb = RgbImage::from_fn(40, 30, |x, y| Rgb([(x * 6) as u8, (y * 8) as u8, ((x + y) * 3) as u8]));
+    let expected = crop_gray_to_percent(&crate::red_green_as_grey(&rgb), DEFAULT_CROP);
+    assert_eq!(Pipeline::default().apply(&rgb), expected);
+  }
+}
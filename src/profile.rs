@@ -0,0 +1,111 @@
+//! Aggregating metric timings over a run, to judge which metrics are affordable in real time
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::analyzer::MetricTimings;
+
+/// What one metric cost over all the frames it was measured on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricCost {
+  pub count: u64,
+  pub total: Duration,
+  pub max: Duration,
+}
+
+impl MetricCost {
+  pub fn mean(&self) -> Duration {
+    if self.count == 0 {
+      Duration::ZERO
+    } else {
+      self.total.div_f64(self.count as f64)
+    }
+  }
+
+  fn add(&mut self, elapsed: Duration) {
+    self.count += 1;
+    self.total += elapsed;
+    self.max = self.max.max(elapsed);
+  }
+
+  fn merge(&mut self, other: &MetricCost) {
+    self.count += other.count;
+    self.total += other.total;
+    self.max = self.max.max(other.max);
+  }
+}
+
+/// Per-metric costs, accumulated frame by frame.
+/// Profilers of parallel workers can be combined with `merge`.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+  costs: BTreeMap<&'static str, MetricCost>,
+  frames: u64,
+}
+
+impl Profiler {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add the timings of one frame
+  pub fn record(&mut self, timings: &MetricTimings) {
+    self.frames += 1;
+    for (name, elapsed) in timings {
+      self.costs.entry(name).or_default().add(*elapsed);
+    }
+  }
+
+  /// Add everything recorded by `other`
+  pub fn merge(&mut self, other: &Profiler) {
+    self.frames += other.frames;
+    for (name, cost) in &other.costs {
+      self.costs.entry(name).or_default().merge(cost);
+    }
+  }
+
+  /// Number of frames recorded
+  pub fn frames(&self) -> u64 {
+    self.frames
+  }
+
+  /// Cost of each metric, by name
+  pub fn costs(&self) -> impl Iterator<Item = (&'static str, &MetricCost)> + '_ {
+    self.costs.iter().map(|(name, cost)| (*name, cost))
+  }
+
+  pub fn cost(&self, name: &str) -> Option<&MetricCost> {
+    self.costs.get(name)
+  }
+
+  /// Time spent on all metrics, per frame
+  pub fn mean_frame_cost(&self) -> Duration {
+    if self.frames == 0 {
+      return Duration::ZERO;
+    }
+    let total: Duration = self.costs.values().map(|cost| cost.total).sum();
+    total.div_f64(self.frames as f64)
+  }
+}
+
+/// A table of metrics, costliest first
+impl fmt::Display for Profiler {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut costs: Vec<_> = self.costs().collect();
+    costs.sort_by_key(|(_, cost)| Reverse(cost.total));
+    let total: Duration = costs.iter().map(|(_, cost)| cost.total).sum();
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+
+    writeln!(f, "{:<20} {:>8} {:>10} {:>10} {:>10} {:>6}",
+             "metric", "frames", "mean ms", "max ms", "total s", "share")?;
+    for (name, cost) in costs {
+      let share = if total.is_zero() { 0.0 } else { cost.total.as_secs_f64() / total.as_secs_f64() };
+      writeln!(f, "{:<20} {:>8} {:>10.3} {:>10.3} {:>10.2} {:>5.1}%",
+               name, cost.count, millis(cost.mean()), millis(cost.max), cost.total.as_secs_f64(), share * 100.0)?;
+    }
+    writeln!(f, "{:<20} {:>8} {:>10.3} {:>10} {:>10.2}",
+             "per frame", self.frames, millis(self.mean_frame_cost()), "", total.as_secs_f64())
+  }
+}
@@ -0,0 +1,324 @@
+//! Classifying frames as nominal, based on statistics of known-good frames
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::analyzer::ImageMetric;
+use crate::MonoImageQAttributes;
+
+/// Scale factor that makes the median absolute deviation
+/// comparable to the standard deviation of normally distributed data
+pub(crate) const MAD_TO_STDDEV: f64 = 1.4826;
+
+/// The attributes of `MonoImageQAttributes` that a model can judge
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityAttribute {
+  MeanIntensity,
+  HistSpread,
+  HistFlatness,
+  Sharpness,
+  LaplacianVariance,
+  Tenengrad,
+  Brenner,
+  HfRatio,
+  BlurAnisotropy,
+  BlurExtent,
+  NoiseSigma,
+  #[serde(rename = "blockiness_8")]
+  Blockiness8,
+  #[serde(rename = "blockiness_16")]
+  Blockiness16,
+  Ringing,
+  DarkPercent,
+  BrightPercent,
+  CornerCountF12,
+  CornerCountF9,
+}
+
+impl QualityAttribute {
+  /// The attributes judged by the default model
+  pub const DEFAULT_SET: [QualityAttribute; 3] = [
+    QualityAttribute::MeanIntensity,
+    QualityAttribute::HistSpread,
+    QualityAttribute::CornerCountF12,
+  ];
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      QualityAttribute::MeanIntensity => "mean_intensity",
+      QualityAttribute::HistSpread => "hist_spread",
+      QualityAttribute::HistFlatness => "hist_flatness",
+      QualityAttribute::Sharpness => "sharpness",
+      QualityAttribute::LaplacianVariance => "laplacian_variance",
+      QualityAttribute::Tenengrad => "tenengrad",
+      QualityAttribute::Brenner => "brenner",
+      QualityAttribute::HfRatio => "hf_ratio",
+      QualityAttribute::BlurAnisotropy => "blur_anisotropy",
+      QualityAttribute::BlurExtent => "blur_extent",
+      QualityAttribute::NoiseSigma => "noise_sigma",
+      QualityAttribute::Blockiness8 => "blockiness_8",
+      QualityAttribute::Blockiness16 => "blockiness_16",
+      QualityAttribute::Ringing => "ringing",
+      QualityAttribute::DarkPercent => "dark_percent",
+      QualityAttribute::BrightPercent => "bright_percent",
+      QualityAttribute::CornerCountF12 => "corner_count_f12",
+      QualityAttribute::CornerCountF9 => "corner_count_f9",
+    }
+  }
+
+  /// The analyzer metric that measures this attribute
+  pub fn metric(&self) -> ImageMetric {
+    match self {
+      QualityAttribute::MeanIntensity | QualityAttribute::HistSpread => ImageMetric::Histogram,
+      QualityAttribute::HistFlatness => ImageMetric::Entropy,
+      QualityAttribute::Sharpness => ImageMetric::Sharpness,
+      QualityAttribute::LaplacianVariance => ImageMetric::Laplacian,
+      QualityAttribute::Tenengrad => ImageMetric::Tenengrad,
+      QualityAttribute::Brenner => ImageMetric::Brenner,
+      QualityAttribute::HfRatio => ImageMetric::Spectral,
+      QualityAttribute::BlurAnisotropy | QualityAttribute::BlurExtent => ImageMetric::Blur,
+      QualityAttribute::NoiseSigma => ImageMetric::Noise,
+      QualityAttribute::Blockiness8 | QualityAttribute::Blockiness16 => ImageMetric::Blockiness,
+      QualityAttribute::Ringing => ImageMetric::Ringing,
+      QualityAttribute::DarkPercent | QualityAttribute::BrightPercent => ImageMetric::Exposure,
+      QualityAttribute::CornerCountF12 => ImageMetric::CornersFast12,
+      QualityAttribute::CornerCountF9 => ImageMetric::CornersFast9,
+    }
+  }
+
+  /// Extract this attribute's value from measured image attributes
+  pub fn value(&self, qattrs: &MonoImageQAttributes) -> f32 {
+    match self {
+      QualityAttribute::MeanIntensity => qattrs.mean_intensity as f32,
+      QualityAttribute::HistSpread => qattrs.hist_spread as f32,
+      QualityAttribute::HistFlatness => qattrs.hist_flatness as f32,
+      QualityAttribute::Sharpness => qattrs.sharpness,
+      QualityAttribute::LaplacianVariance => qattrs.laplacian_variance as f32,
+      QualityAttribute::Tenengrad => qattrs.tenengrad as f32,
+      QualityAttribute::Brenner => qattrs.brenner as f32,
+      QualityAttribute::HfRatio => qattrs.hf_ratio as f32,
+      QualityAttribute::BlurAnisotropy => qattrs.blur_anisotropy as f32,
+      QualityAttribute::BlurExtent => qattrs.blur_extent as f32,
+      QualityAttribute::NoiseSigma => qattrs.noise_sigma as f32,
+      QualityAttribute::Blockiness8 => qattrs.blockiness_8 as f32,
+      QualityAttribute::Blockiness16 => qattrs.blockiness_16 as f32,
+      QualityAttribute::Ringing => qattrs.ringing as f32,
+      QualityAttribute::DarkPercent => qattrs.dark_percent,
+      QualityAttribute::BrightPercent => qattrs.bright_percent,
+      QualityAttribute::CornerCountF12 => qattrs.corner_count_f12 as f32,
+      QualityAttribute::CornerCountF9 => qattrs.corner_count_f9 as f32,
+    }
+  }
+}
+
+/// How the center and spread of each attribute are estimated
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FitMethod {
+  /// Mean and standard deviation
+  MeanStdDev,
+  /// Median and (scaled) median absolute deviation: robust to outlier frames
+  MedianMad,
+}
+
+/// Center and spread of one attribute over nominal frames
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct AttributeStats {
+  pub center: f32,
+  pub spread: f32,
+}
+
+impl AttributeStats {
+  fn fit(values: &mut [f32], method: FitMethod) -> Self {
+    let (center, spread) = match method {
+      FitMethod::MeanStdDev => {
+        let count = values.len() as f32;
+        let mean = values.iter().sum::<f32>() / count;
+        let variance = values.iter().map(|val| (val - mean) * (val - mean)).sum::<f32>() / count;
+        (mean, variance.sqrt())
+      }
+      FitMethod::MedianMad => {
+        let center = median(values);
+        let mut deviations: Vec<f32> = values.iter().map(|val| (val - center).abs()).collect();
+        (center, MAD_TO_STDDEV as f32 * median(&mut deviations))
+      }
+    };
+    Self { center, spread }
+  }
+
+  pub fn zscore(&self, val: f32) -> f32 {
+    // a constant attribute in the training frames would otherwise divide by zero
+    (val - self.center) / self.spread.max(f32::EPSILON)
+  }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+  values.sort_by(|a, b| a.total_cmp(b));
+  let mid = values.len() / 2;
+  if values.len().is_multiple_of(2) {
+    (values[mid - 1] + values[mid]) / 2.0
+  } else {
+    values[mid]
+  }
+}
+
+/// The outcome of judging one frame against a `QualityModel`
+#[derive(Debug, Clone)]
+pub struct Classification {
+  pub nominal: bool,
+  /// z-score of each attribute the model judges
+  pub zscores: Vec<(QualityAttribute, f32)>,
+}
+
+impl Classification {
+  /// The attributes whose z-scores fall outside the model's limit
+  pub fn outliers(&self, max_zscore: f32) -> impl Iterator<Item = &(QualityAttribute, f32)> {
+    self.zscores.iter().filter(move |(_, zscore)| zscore.abs() > max_zscore)
+  }
+}
+
+/// Statistics of known-good frames, used to decide whether other frames are nominal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QualityModel {
+  pub method: FitMethod,
+  /// Frames with any attribute z-score beyond +/- this are not nominal
+  pub max_zscore: f32,
+  pub attributes: BTreeMap<QualityAttribute, AttributeStats>,
+}
+
+impl Default for QualityModel {
+  /// Hand-tuned for the original approach camera
+  fn default() -> Self {
+    let attributes = BTreeMap::from([
+      (QualityAttribute::MeanIntensity, AttributeStats { center: 117.0, spread: 9.0 }),
+      (QualityAttribute::HistSpread, AttributeStats { center: 0.5, spread: 0.09 }),
+      (QualityAttribute::CornerCountF12, AttributeStats { center: 4000.0, spread: 1000.0 }),
+    ]);
+    Self { method: FitMethod::MeanStdDev, max_zscore: 2.0, attributes }
+  }
+}
+
+impl QualityModel {
+  /// Fit a model to the given attributes of known-good frames.
+  /// Returns None if there are no samples.
+  pub fn fit(samples: &[MonoImageQAttributes], attributes: &[QualityAttribute], method: FitMethod)
+    -> Option<Self>
+  {
+    if samples.is_empty() {
+      return None;
+    }
+    let attributes = attributes.iter()
+      .map(|attr| {
+        let mut values: Vec<f32> = samples.iter().map(|qattrs| attr.value(qattrs)).collect();
+        (*attr, AttributeStats::fit(&mut values, method))
+      })
+      .collect();
+    Some(Self { method, max_zscore: 2.0, attributes })
+  }
+
+  pub fn load(path: &Path) -> std::io::Result<Self> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+  }
+
+  pub fn save(&self, path: &Path) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+    Ok(())
+  }
+
+  /// Load a model from the given path, or fall back to the default model
+  pub fn load_or_default(path: Option<&Path>) -> std::io::Result<Self> {
+    match path {
+      Some(path) => Self::load(path),
+      None => Ok(Self::default()),
+    }
+  }
+
+  /// Judge a frame by the z-scores of each modeled attribute
+  pub fn classify(&self, qattrs: &MonoImageQAttributes) -> Classification {
+    let zscores: Vec<(QualityAttribute, f32)> = self.attributes.iter()
+      .map(|(attr, stats)| (*attr, stats.zscore(attr.value(qattrs))))
+      .collect();
+    let nominal = zscores.iter().all(|(_, zscore)| zscore.abs() <= self.max_zscore);
+    Classification { nominal, zscores }
+  }
+
+  /// Judge a frame whose attributes are looked up by `value`, eg from a stored record.
+  /// None if any modeled attribute is missing.
+  pub fn classify_values<F>(&self, value: F) -> Option<Classification>
+    where F: Fn(QualityAttribute) -> Option<f32>
+  {
+    let zscores = self.attributes.iter()
+      .map(|(attr, stats)| Some((*attr, stats.zscore(value(*attr)?))))
+      .collect::<Option<Vec<(QualityAttribute, f32)>>>()?;
+    let nominal = zscores.iter().all(|(_, zscore)| zscore.abs() <= self.max_zscore);
+    Some(Classification { nominal, zscores })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn frame(mean_intensity: u8) -> MonoImageQAttributes {
+    MonoImageQAttributes { mean_intensity, ..Default::default() }
+  }
+
+  fn samples(intensities: &[u8]) -> Vec<MonoImageQAttributes> {
+    intensities.iter().map(|val| frame(*val)).collect()
+  }
+
+  fn fit(intensities: &[u8], method: FitMethod) -> QualityModel {
+    QualityModel::fit(&samples(intensities), &[QualityAttribute::MeanIntensity], method).unwrap()
+  }
+
+  #[test]
+  fn fits_center_and_spread() {
+    let stats = fit(&[10, 20, 30, 40, 100], FitMethod::MeanStdDev).attributes[&QualityAttribute::MeanIntensity];
+    assert_eq!(stats.center, 40.0);
+    assert!((stats.spread - 1000f32.sqrt()).abs() < 1e-4, "stddev {}", stats.spread);
+
+    // the outlier barely moves the robust estimates
+    let stats = fit(&[10, 20, 30, 40, 100], FitMethod::MedianMad).attributes[&QualityAttribute::MeanIntensity];
+    assert_eq!(stats.center, 30.0);
+    assert!((stats.spread - 10.0 * MAD_TO_STDDEV as f32).abs() < 1e-4, "scaled MAD {}", stats.spread);
+
+    assert!(QualityModel::fit(&[], &QualityAttribute::DEFAULT_SET, FitMethod::MeanStdDev).is_none());
+  }
+
+  #[test]
+  fn zero_spread_is_guarded() {
+    for method in [FitMethod::MeanStdDev, FitMethod::MedianMad] {
+      let stats = fit(&[50, 50, 50], method).attributes[&QualityAttribute::MeanIntensity];
+      assert_eq!(stats.spread, 0.0);
+      assert_eq!(stats.zscore(50.0), 0.0);
+      let zscore = stats.zscore(51.0);
+      assert!(zscore.is_finite() && zscore > 1e6, "zscore {}", zscore);
+    }
+  }
+
+  #[test]
+  fn classify_flags_outliers() {
+    let model = fit(&[10, 20, 30, 40, 100], FitMethod::MedianMad);
+
+    let inlier = model.classify(&frame(35));
+    assert!(inlier.nominal);
+    assert_eq!(inlier.outliers(model.max_zscore).count(), 0);
+
+    let outlier = model.classify(&frame(100));
+    assert!(!outlier.nominal);
+    let outliers: Vec<QualityAttribute> = outlier.outliers(model.max_zscore).map(|(attr, _)| *attr).collect();
+    assert_eq!(outliers, [QualityAttribute::MeanIntensity]);
+
+    // stored values are judged the same way
+    let stored = model.classify_values(|_| Some(100.0)).unwrap();
+    assert_eq!(stored.nominal, outlier.nominal);
+    assert!(model.classify_values(|_| None).is_none());
+  }
+}
@@ -0,0 +1,424 @@
+//! Per-frame records as table rows, written with the same columns in every output format
+
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::analyzer::ImageMetric;
+use crate::manifest::{SegmentDescriptor, SegmentKind};
+use crate::quality::{Classification, QualityAttribute, QualityModel};
+use crate::sequence::FrameRecord;
+
+/// The type of a column's values, for formats that declare one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+  Int,
+  Float,
+  Bool,
+  Text,
+}
+
+/// Columns of a serialized `FrameRecord` without its comparison, in output order
+pub const FRAME_COLUMNS: [(&str, ColumnKind); 25] = [
+  ("frame", ColumnKind::Int),
+  ("pts", ColumnKind::Int),
+  ("width", ColumnKind::Int),
+  ("height", ColumnKind::Int),
+  ("sharpness", ColumnKind::Float),
+  ("laplacian_variance", ColumnKind::Float),
+  ("tenengrad", ColumnKind::Float),
+  ("brenner", ColumnKind::Float),
+  ("hf_ratio", ColumnKind::Float),
+  ("blur_direction", ColumnKind::Float),
+  ("blur_anisotropy", ColumnKind::Float),
+  ("blur_extent", ColumnKind::Float),
+  ("noise_sigma", ColumnKind::Float),
+  ("blockiness_8", ColumnKind::Float),
+  ("blockiness_16", ColumnKind::Float),
+  ("ringing", ColumnKind::Float),
+  ("mean_intensity", ColumnKind::Int),
+  ("hist_spread", ColumnKind::Float),
+  ("hist_flatness", ColumnKind::Float),
+  ("dark_pixel_count", ColumnKind::Int),
+  ("bright_pixel_count", ColumnKind::Int),
+  ("dark_percent", ColumnKind::Float),
+  ("bright_percent", ColumnKind::Float),
+  ("corner_count_f12", ColumnKind::Int),
+  ("corner_count_f9", ColumnKind::Int),
+];
+
+/// Columns of a serialized `ImgComparison`, null for the first frame of a sequence
+/// and for metrics that weren't measured
+pub const COMPARISON_COLUMNS: [(&str, ColumnKind); 9] = [
+  ("rms_error", ColumnKind::Float),
+  ("psnr", ColumnKind::Float),
+  ("ssim_score", ColumnKind::Float),
+  ("ms_ssim_score", ColumnKind::Float),
+  ("gmsd", ColumnKind::Float),
+  ("hsim_score", ColumnKind::Float),
+  ("hellinger_distance", ColumnKind::Float),
+  ("chi_square_distance", ColumnKind::Float),
+  ("bhattacharyya_distance", ColumnKind::Float),
+];
+
+/// Columns of a serialized `SegmentContext`, in output order
+pub const SEGMENT_COLUMNS: [(&str, ColumnKind); 5] = [
+  ("video_id", ColumnKind::Text),
+  ("segment", ColumnKind::Text),
+  ("kind", ColumnKind::Text),
+  ("icao", ColumnKind::Text),
+  ("runway", ColumnKind::Text),
+];
+
+#[derive(Debug, Clone)]
+pub struct Column {
+  pub name: String,
+  pub kind: ColumnKind,
+}
+
+impl Column {
+  pub fn new(name: &str, kind: ColumnKind) -> Self {
+    Self { name: name.to_string(), kind }
+  }
+}
+
+/// Identifies the manifest segment a frame belongs to, when records of many segments share a table
+#[derive(Serialize, Debug, Clone)]
+pub struct SegmentContext {
+  pub video_id: String,
+  /// `<kind>-<start frame>-<end frame>`, unique within a video
+  pub segment: String,
+  pub kind: SegmentKind,
+  pub icao: String,
+  pub runway: String,
+}
+
+impl SegmentContext {
+  pub fn new(seg: &SegmentDescriptor, video_id: &str) -> Self {
+    Self {
+      video_id: video_id.to_string(),
+      segment: format!("{}-{}-{}", seg.kind, seg.start_frame, seg.end_frame),
+      kind: seg.kind,
+      icao: seg.icao.clone(),
+      runway: seg.runway_designator.clone(),
+    }
+  }
+
+  /// Add this context's columns to a row
+  pub fn extend_row(&self, row: &mut Row) {
+    if let Ok(Value::Object(fields)) = serde_json::to_value(self) {
+      row.extend(fields);
+    }
+  }
+}
+
+/// One table row: column name to value. Columns missing from a row are written as null.
+pub type Row = Map<String, Value>;
+
+/// Name of the column holding an attribute's z-score
+pub fn zscore_column(attr: QualityAttribute) -> String {
+  format!("z_{}", attr.name())
+}
+
+/// The ordered columns of a table of frame records
+#[derive(Debug, Clone)]
+pub struct RecordSchema {
+  pub columns: Vec<Column>,
+}
+
+impl RecordSchema {
+  /// Frame record columns followed by the model's classification columns
+  pub fn new(model: &QualityModel) -> Self {
+    let mut columns: Vec<Column> = FRAME_COLUMNS.iter().chain(COMPARISON_COLUMNS.iter())
+      .map(|(name, kind)| Column::new(name, *kind))
+      .collect();
+    columns.push(Column::new("nominal", ColumnKind::Bool));
+    columns.extend(model.attributes.keys().map(|attr| Column::new(&zscore_column(*attr), ColumnKind::Float)));
+    Self { columns }
+  }
+
+  /// Lead with the `SegmentContext` columns, for tables spanning many segments
+  pub fn with_segment_context(mut self) -> Self {
+    let segment_columns = SEGMENT_COLUMNS.iter().map(|(name, kind)| Column::new(name, *kind));
+    self.columns.splice(0..0, segment_columns);
+    self
+  }
+
+  pub fn column_names(&self) -> Vec<String> {
+    self.columns.iter().map(|col| col.name.clone()).collect()
+  }
+
+  /// Build the row for a frame record and its classification
+  pub fn row(&self, record: &FrameRecord, class: &Classification) -> Row {
+    let mut row = match serde_json::to_value(record) {
+      Ok(Value::Object(row)) => row,
+      _ => Row::new(),
+    };
+    // attributes that weren't measured are null rather than zero
+    for metric in ImageMetric::ALL.iter().filter(|metric| !record.qattrs.measured.contains(metric)) {
+      for field in metric.fields() {
+        row.insert(field.to_string(), Value::Null);
+      }
+    }
+    set_classification(&mut row, class);
+    row
+  }
+}
+
+/// Set a row's classification columns
+pub fn set_classification(row: &mut Row, class: &Classification) {
+  row.insert("nominal".to_string(), Value::from(class.nominal));
+  for (attr, zscore) in &class.zscores {
+    row.insert(zscore_column(*attr), Value::from(*zscore));
+  }
+}
+
+/// Output formats for tables of records
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordFormat {
+  Csv,
+  /// One JSON object per line
+  JsonLines,
+  /// A single JSON object mapping each column name to an array of values
+  Columnar,
+  /// Apache Parquet, with typed columns
+  #[cfg(feature = "parquet")]
+  Parquet,
+}
+
+impl RecordFormat {
+  pub fn extension(&self) -> &'static str {
+    match self {
+      RecordFormat::Csv => "csv",
+      RecordFormat::JsonLines => "jsonl",
+      RecordFormat::Columnar => "columns.json",
+      #[cfg(feature = "parquet")]
+      RecordFormat::Parquet => "parquet",
+    }
+  }
+}
+
+/// Writes rows of a table to some output
+pub trait RecordWriter: Send {
+  fn write_row(&mut self, row: &Row) -> io::Result<()>;
+  /// Write anything still buffered and flush the output
+  fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Create a writer for the given format, writing any header immediately
+pub fn record_writer<'a, W: Write + Send + 'a>(format: RecordFormat, schema: &RecordSchema, out: W)
+  -> io::Result<Box<dyn RecordWriter + 'a>>
+{
+  Ok(match format {
+    RecordFormat::Csv => Box::new(CsvWriter::new(schema.column_names(), out)?),
+    RecordFormat::JsonLines => Box::new(JsonLinesWriter { columns: schema.column_names(), out }),
+    RecordFormat::Columnar => Box::new(ColumnarWriter::new(schema.column_names(), out)),
+    #[cfg(feature = "parquet")]
+    RecordFormat::Parquet => Box::new(crate::parquet_export::ParquetWriter::new(schema.columns.clone(), out)?),
+  })
+}
+
+fn cell<'r>(row: &'r Row, column: &str) -> &'r Value {
+  row.get(column).unwrap_or(&Value::Null)
+}
+
+pub struct CsvWriter<W: Write> {
+  columns: Vec<String>,
+  out: W,
+}
+
+impl<W: Write> CsvWriter<W> {
+  pub fn new(columns: Vec<String>, mut out: W) -> io::Result<Self> {
+    writeln!(out, "{}", columns.join(","))?;
+    Ok(Self { columns, out })
+  }
+}
+
+/// Format a value as a CSV field, quoting only where needed
+fn csv_field(value: &Value) -> String {
+  match value {
+    Value::Null => String::new(),
+    Value::String(text) if text.contains([',', '"', '\n', '\r']) => {
+      format!("\"{}\"", text.replace('"', "\"\""))
+    }
+    Value::String(text) => text.clone(),
+    other => other.to_string(),
+  }
+}
+
+impl<W: Write + Send> RecordWriter for CsvWriter<W> {
+  fn write_row(&mut self, row: &Row) -> io::Result<()> {
+    let fields: Vec<String> = self.columns.iter().map(|col| csv_field(cell(row, col))).collect();
+    writeln!(self.out, "{}", fields.join(","))
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    self.out.flush()
+  }
+}
+
+pub struct JsonLinesWriter<W: Write> {
+  columns: Vec<String>,
+  out: W,
+}
+
+impl<W: Write + Send> RecordWriter for JsonLinesWriter<W> {
+  fn write_row(&mut self, row: &Row) -> io::Result<()> {
+    // written by hand to keep the schema's column order
+    let mut line = String::from("{");
+    for (pos, col) in self.columns.iter().enumerate() {
+      if pos > 0 {
+        line.push(',');
+      }
+      line.push_str(&Value::from(col.as_str()).to_string());
+      line.push(':');
+      line.push_str(&cell(row, col).to_string());
+    }
+    line.push('}');
+    writeln!(self.out, "{}", line)
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    self.out.flush()
+  }
+}
+
+/// Buffers every row, and writes all columns at `finish`
+pub struct ColumnarWriter<W: Write> {
+  columns: Vec<String>,
+  values: Vec<Vec<Value>>,
+  out: W,
+}
+
+impl<W: Write> ColumnarWriter<W> {
+  pub fn new(columns: Vec<String>, out: W) -> Self {
+    let values = vec![Vec::new(); columns.len()];
+    Self { columns, values, out }
+  }
+}
+
+impl<W: Write + Send> RecordWriter for ColumnarWriter<W> {
+  fn write_row(&mut self, row: &Row) -> io::Result<()> {
+    for (col, values) in self.columns.iter().zip(self.values.iter_mut()) {
+      values.push(cell(row, col).clone());
+    }
+    Ok(())
+  }
+
+  fn finish(&mut self) -> io::Result<()> {
+    writeln!(self.out, "{{")?;
+    for (pos, (col, values)) in self.columns.iter().zip(self.values.drain(..)).enumerate() {
+      let separator = if pos + 1 < self.columns.len() { "," } else { "" };
+      writeln!(self.out, "  {}: {}{}", Value::from(col.as_str()), Value::Array(values), separator)?;
+    }
+    writeln!(self.out, "}}")?;
+    self.out.flush()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use image::GrayImage;
+
+  use super::*;
+  use crate::analyzer::Analyzer;
+  use crate::metrics::ComparisonMetric;
+  use crate::sequence::SequenceAnalyzer;
+
+  fn frame(seed: u32) -> GrayImage {
+    GrayImage::from_fn(64, 64, |x, y| image::Luma([((x * 7 + y * 13 + seed * 31) % 256) as u8]))
+  }
+
+  /// Rows of a two-frame sequence, measuring only the histogram and SSIM
+  fn rows(schema: &RecordSchema, model: &QualityModel) -> Vec<Row> {
+    let mut sequence = SequenceAnalyzer::new(Analyzer::new().with(ImageMetric::Histogram), &[ComparisonMetric::Ssim]);
+    (0..2).map(|index| {
+      let record = sequence.analyze(index, index as i64 * 512, frame(index as u32)).unwrap();
+      schema.row(&record, &model.classify(&record.qattrs))
+    }).collect()
+  }
+
+  fn written(format: RecordFormat, schema: &RecordSchema, rows: &[Row]) -> String {
+    let mut out = Vec::new();
+    {
+      let mut writer = record_writer(format, schema, &mut out).unwrap();
+      for row in rows {
+        writer.write_row(row).unwrap();
+      }
+      writer.finish().unwrap();
+    }
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn csv_quotes_only_where_needed() {
+    assert_eq!(csv_field(&Value::from("KSFO")), "KSFO");
+    assert_eq!(csv_field(&Value::from("28L, 28R")), "\"28L, 28R\"");
+    assert_eq!(csv_field(&Value::from("the \"glare\" frames")), "\"the \"\"glare\"\" frames\"");
+    assert_eq!(csv_field(&Value::from("two\nlines")), "\"two\nlines\"");
+    assert_eq!(csv_field(&Value::Null), "");
+    assert_eq!(csv_field(&Value::from(1.5)), "1.5");
+    assert_eq!(csv_field(&Value::from(false)), "false");
+  }
+
+  #[test]
+  fn header_follows_the_column_constants() {
+    let model = QualityModel::default();
+    let schema = RecordSchema::new(&model).with_segment_context();
+    let mut expected: Vec<String> = SEGMENT_COLUMNS.iter().chain(&FRAME_COLUMNS).chain(&COMPARISON_COLUMNS)
+      .map(|(name, _)| name.to_string())
+      .collect();
+    expected.push("nominal".to_string());
+    expected.extend(model.attributes.keys().map(|attr| zscore_column(*attr)));
+    assert_eq!(schema.column_names(), expected);
+
+    let csv = written(RecordFormat::Csv, &schema, &[]);
+    assert_eq!(csv, format!("{}\n", expected.join(",")));
+  }
+
+  #[test]
+  fn unmeasured_metrics_are_null() {
+    let model = QualityModel::default();
+    let schema = RecordSchema::new(&model);
+    let rows = rows(&schema, &model);
+    for row in &rows {
+      assert!(row["mean_intensity"].is_number());
+      assert_eq!(row["width"], Value::from(64));
+      for column in ["sharpness", "noise_sigma", "corner_count_f12", "dark_percent"] {
+        assert_eq!(row[column], Value::Null, "{}", column);
+      }
+    }
+    // the first frame has nothing to be compared with
+    assert!(cell(&rows[0], "ssim_score").is_null());
+    assert!(rows[1]["ssim_score"].is_number());
+    assert!(cell(&rows[1], "psnr").is_null());
+
+    let csv = written(RecordFormat::Csv, &schema, &rows);
+    let header: Vec<&str> = csv.lines().next().unwrap().split(',').collect();
+    let fields: Vec<&str> = csv.lines().nth(1).unwrap().split(',').collect();
+    let field = |column: &str| fields[header.iter().position(|col| *col == column).unwrap()];
+    assert_eq!(field("sharpness"), "");
+    assert_eq!(field("ssim_score"), "");
+    assert_ne!(field("mean_intensity"), "");
+  }
+
+  #[test]
+  fn json_lines_and_columnar_hold_the_same_values() {
+    let model = QualityModel::default();
+    let schema = RecordSchema::new(&model);
+    let rows = rows(&schema, &model);
+
+    let lines: Vec<Row> = written(RecordFormat::JsonLines, &schema, &rows).lines()
+      .map(|line| serde_json::from_str(line).unwrap())
+      .collect();
+    let columns: Row = serde_json::from_str(&written(RecordFormat::Columnar, &schema, &rows)).unwrap();
+    assert_eq!(lines.len(), rows.len());
+    for name in schema.column_names() {
+      let by_row: Vec<&Value> = lines.iter().map(|line| &line[&name]).collect();
+      let by_column: Vec<&Value> = columns[&name].as_array().unwrap().iter().collect();
+      assert_eq!(by_row, by_column, "{}", name);
+    }
+  }
+}
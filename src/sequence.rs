@@ -0,0 +1,128 @@
+//! Analysis of frame sequences, where each frame is also compared with its predecessor
+
+use std::sync::Arc;
+
+use image::GrayImage;
+use image_compare::CompareError;
+use serde::Serialize;
+
+use crate::{compare_images_timed, ImgComparison, MonoImageQAttributes};
+use crate::analyzer::{Analyzer, MetricTimings};
+use crate::metrics::ComparisonMetric;
+
+/// The analysis of one frame within a sequence.
+/// Serializes flat, as a single row of `records::FRAME_COLUMNS` and `records::COMPARISON_COLUMNS`.
+#[derive(Debug, Serialize)]
+pub struct FrameRecord {
+  /// Frame index within the video
+  #[serde(rename = "frame")]
+  pub index: usize,
+  /// Presentation timestamp, in the video stream's time base
+  pub pts: i64,
+  #[serde(flatten)]
+  pub qattrs: MonoImageQAttributes,
+  /// Comparison against the previous frame, or None for the first frame of a sequence
+  #[serde(flatten)]
+  pub comparison: Option<ImgComparison>,
+  /// What measuring `qattrs`, then the comparison, cost
+  #[serde(skip)]
+  pub timings: MetricTimings,
+}
+
+/// A preprocessed frame paired with its predecessor.
+/// Analyzing a pair needs no other state, so pairs can be analyzed on any thread.
+#[derive(Debug, Clone)]
+pub struct FramePair {
+  pub index: usize,
+  pub pts: i64,
+  pub current: Arc<GrayImage>,
+  pub prior: Option<Arc<GrayImage>>,
+  pub analyzer: Arc<Analyzer>,
+  /// Metrics comparing the frame with its predecessor
+  pub metrics: Arc<[ComparisonMetric]>,
+}
+
+impl FramePair {
+  /// Fails if the frame can't be compared with its predecessor, eg after a change of resolution
+  pub fn analyze(&self) -> Result<FrameRecord, CompareError> {
+    let mut analysis = self.analyzer.analyze(&self.current);
+    let comparison = match &self.prior {
+      Some(prior) => {
+        let (comparison, _, timings) = compare_images_timed(prior, &self.current, &self.metrics, false)?;
+        analysis.timings.extend(timings);
+        Some(comparison)
+      }
+      None => None,
+    };
+    Ok(FrameRecord {
+      index: self.index,
+      pts: self.pts,
+      qattrs: analysis.qattrs,
+      comparison,
+      timings: analysis.timings,
+    })
+  }
+}
+
+/// Owns the temporal state of a frame sequence: the previous frame.
+/// Call `reset` (or use a new analyzer) at the start of each segment,
+/// so that the first frame of a segment isn't compared with the end of another.
+#[derive(Debug)]
+pub struct SequenceAnalyzer {
+  prior: Option<Arc<GrayImage>>,
+  analyzer: Arc<Analyzer>,
+  metrics: Arc<[ComparisonMetric]>,
+}
+
+impl Default for SequenceAnalyzer {
+  /// Analyze frames with `Analyzer::fast`, comparing them by the default metrics
+  fn default() -> Self {
+    Self::new(Analyzer::fast(), &ComparisonMetric::DEFAULT_SET)
+  }
+}
+
+impl SequenceAnalyzer {
+  /// Analyze each frame with `analyzer`, and compare it with its predecessor by `metrics`
+  pub fn new(analyzer: Analyzer, metrics: &[ComparisonMetric]) -> Self {
+    Self { prior: None, analyzer: Arc::new(analyzer), metrics: metrics.into() }
+  }
+
+  /// Forget the previous frame
+  pub fn reset(&mut self) {
+    self.prior = None;
+  }
+
+  /// Pair the next frame of the sequence with its predecessor,
+  /// for analysis now or on another thread
+  pub fn pair(&mut self, index: usize, pts: i64, gray_img: GrayImage) -> FramePair {
+    let current = Arc::new(gray_img);
+    let prior = self.prior.replace(current.clone());
+    FramePair { index, pts, current, prior, analyzer: self.analyzer.clone(), metrics: self.metrics.clone() }
+  }
+
+  /// Analyze the next frame of the sequence
+  pub fn analyze(&mut self, index: usize, pts: i64, gray_img: GrayImage) -> Result<FrameRecord, CompareError> {
+    self.pair(index, pts, gray_img).analyze()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn frame(seed: u32) -> GrayImage {
+    GrayImage::from_fn(64, 64, |x, y| image::Luma([((x * 7 + y * 13 + seed * 31) % 256) as u8]))
+  }
+
+  #[test]
+  fn reset_starts_a_new_sequence() {
+    let mut sequence = SequenceAnalyzer::default();
+    assert!(sequence.analyze(0, 0, frame(0)).unwrap().comparison.is_none());
+    assert!(sequence.analyze(1, 1, frame(1)).unwrap().comparison.is_some());
+
+    sequence.reset();
+    let first = sequence.analyze(100, 100, frame(2)).unwrap();
+    assert!(first.comparison.is_none());
+    assert!(sequence.analyze(101, 101, frame(3)).unwrap().comparison.is_some());
+  }
+}
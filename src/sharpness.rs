@@ -0,0 +1,216 @@
+//! Focus measures, for spotting defocused and motion-blurred frames.
+//!
+//! Intensities are scaled to [0, 1] and each measure is a per-pixel mean or a ratio,
+//! so values are comparable between images of different sizes (though not different scales of one scene).
+//! Images too small to measure score zero.
+
+use image::GrayImage;
+
+/// Side of the (power of two) center square whose spectrum `high_frequency_ratio` measures
+const SPECTRUM_SIZE: usize = 256;
+
+/// Frequencies above this, in cycles per pixel, count as high: half the Nyquist frequency
+const HIGH_FREQUENCY_CUTOFF: f64 = 0.25;
+
+/// Intensities scaled to [0, 1], in rows
+fn normalized(img: &GrayImage) -> Vec<f64> {
+  img.as_raw().iter().map(|val| *val as f64 / 255.0).collect()
+}
+
+/// Mean of `measure(x, y)` over the pixels at least `border` from each edge
+fn interior_mean(img: &GrayImage, border: usize, measure: impl Fn(usize, usize) -> f64) -> Option<f64> {
+  let (width, height) = (img.width() as usize, img.height() as usize);
+  if width <= 2 * border || height <= 2 * border {
+    return None;
+  }
+  let mut sum = 0.0;
+  for y in border..height - border {
+    for x in border..width - border {
+      sum += measure(x, y);
+    }
+  }
+  Some(sum / ((width - 2 * border) * (height - 2 * border)) as f64)
+}
+
+/// Variance of the signed 4-neighbour Laplacian.
+/// Unlike `crate::laplacian_variance`, negative responses aren't clamped away.
+pub fn laplacian_variance(img: &GrayImage) -> f64 {
+  let width = img.width() as usize;
+  let pixels = normalized(img);
+  let at = |x: usize, y: usize| pixels[y * width + x];
+  let laplacian = |x: usize, y: usize| {
+    4.0 * at(x, y) - at(x - 1, y) - at(x + 1, y) - at(x, y - 1) - at(x, y + 1)
+  };
+  let Some(mean) = interior_mean(img, 1, laplacian) else { return 0.0 };
+  interior_mean(img, 1, |x, y| (laplacian(x, y) - mean).powi(2)).unwrap_or(0.0)
+}
+
+/// Tenengrad: mean squared Sobel gradient magnitude, with the Sobel response scaled to a per-pixel slope
+pub fn tenengrad(img: &GrayImage) -> f64 {
+  let width = img.width() as usize;
+  let pixels = normalized(img);
+  let at = |x: usize, y: usize| pixels[y * width + x];
+  interior_mean(img, 1, |x, y| {
+    let gx = (at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1))
+      - (at(x - 1, y - 1) + 2.0 * at(x - 1, y) + at(x - 1, y + 1));
+    let gy = (at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1))
+      - (at(x - 1, y - 1) + 2.0 * at(x, y - 1) + at(x + 1, y - 1));
+    (gx * gx + gy * gy) / 64.0
+  }).unwrap_or(0.0)
+}
+
+/// Brenner gradient: mean squared difference between pixels two apart.
+/// Horizontal and vertical differences are averaged, so blur along either axis lowers it.
+pub fn brenner(img: &GrayImage) -> f64 {
+  let width = img.width() as usize;
+  let pixels = normalized(img);
+  let at = |x: usize, y: usize| pixels[y * width + x];
+  interior_mean(img, 1, |x, y| {
+    let dx = at(x + 1, y) - at(x - 1, y);
+    let dy = at(x, y + 1) - at(x, y - 1);
+    (dx * dx + dy * dy) / 2.0
+  }).unwrap_or(0.0)
+}
+
+/// Fraction of the spectral power (excluding DC) above `HIGH_FREQUENCY_CUTOFF`,
+/// measured over a Hann-windowed center square of up to `SPECTRUM_SIZE` pixels
+pub fn high_frequency_ratio(img: &GrayImage) -> f64 {
+  let (width, height) = (img.width() as usize, img.height() as usize);
+  let max_side = width.min(height).min(SPECTRUM_SIZE);
+  if max_side < 8 {
+    return 0.0;
+  }
+  // the largest power of two that fits
+  let side = 1 << max_side.ilog2();
+  let left = (width - side) / 2;
+  let top = (height - side) / 2;
+
+  let mut re: Vec<f64> = (0..side * side)
+    .map(|i| img.get_pixel((left + i % side) as u32, (top + i / side) as u32).0[0] as f64 / 255.0)
+    .collect();
+  let mean = re.iter().sum::<f64>() / re.len() as f64;
+  let hann: Vec<f64> = (0..side)
+    .map(|i| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / side as f64).cos())
+    .collect();
+  for (i, val) in re.iter_mut().enumerate() {
+    *val = (*val - mean) * hann[i % side] * hann[i / side];
+  }
+  let mut im = vec![0.0; side * side];
+  fft_2d(&mut re, &mut im, side);
+
+  let frequency = |k: usize| if k < side / 2 { k as f64 } else { k as f64 - side as f64 } / side as f64;
+  let mut total = 0.0;
+  let mut high = 0.0;
+  for v in 0..side {
+    for u in 0..side {
+      if u == 0 && v == 0 {
+        continue;
+      }
+      let power = re[v * side + u].powi(2) + im[v * side + u].powi(2);
+      total += power;
+      if frequency(u).hypot(frequency(v)) > HIGH_FREQUENCY_CUTOFF {
+        high += power;
+      }
+    }
+  }
+  if total > 0.0 { high / total } else { 0.0 }
+}
+
+/// In-place FFT of a `side` x `side` row-major array, `side` a power of two
+fn fft_2d(re: &mut [f64], im: &mut [f64], side: usize) {
+  for row in 0..side {
+    let span = row * side..(row + 1) * side;
+    fft(&mut re[span.clone()], &mut im[span]);
+  }
+  let mut col_re = vec![0.0; side];
+  let mut col_im = vec![0.0; side];
+  for col in 0..side {
+    for row in 0..side {
+      col_re[row] = re[row * side + col];
+      col_im[row] = im[row * side + col];
+    }
+    fft(&mut col_re, &mut col_im);
+    for row in 0..side {
+      re[row * side + col] = col_re[row];
+      im[row * side + col] = col_im[row];
+    }
+  }
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two
+fn fft(re: &mut [f64], im: &mut [f64]) {
+  let n = re.len();
+  let mut j = 0;
+  for i in 1..n {
+    let mut bit = n >> 1;
+    while j & bit != 0 {
+      j ^= bit;
+      bit >>= 1;
+    }
+    j |= bit;
+    if i < j {
+      re.swap(i, j);
+      im.swap(i, j);
+    }
+  }
+
+  let mut len = 2;
+  while len <= n {
+    let angle = -2.0 * std::f64::consts::PI / len as f64;
+    for start in (0..n).step_by(len) {
+      for k in 0..len / 2 {
+        let (w_im, w_re) = (angle * k as f64).sin_cos();
+        let (a, b) = (start + k, start + k + len / 2);
+        let t_re = re[b] * w_re - im[b] * w_im;
+        let t_im = re[b] * w_im + im[b] * w_re;
+        re[b] = re[a] - t_re;
+        im[b] = im[a] - t_im;
+        re[a] += t_re;
+        im[a] += t_im;
+      }
+    }
+    len <<= 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn impulse_has_a_flat_spectrum() {
+    let mut re = vec![0.0; 16];
+    let mut im = vec![0.0; 16];
+    re[0] = 1.0;
+    fft(&mut re, &mut im);
+    assert!(re.iter().all(|val| (val - 1.0).abs() < 1e-12));
+    assert!(im.iter().all(|val| val.abs() < 1e-12));
+
+    let side = 8;
+    let mut re = vec![0.0; side * side];
+    let mut im = vec![0.0; side * side];
+    re[0] = 1.0;
+    fft_2d(&mut re, &mut im, side);
+    assert!(re.iter().all(|val| (val - 1.0).abs() < 1e-12));
+    assert!(im.iter().all(|val| val.abs() < 1e-12));
+  }
+
+  #[test]
+  fn cosine_lands_in_its_frequency_bins() {
+    let n = 32;
+    let mut re: Vec<f64> = (0..n).map(|i| (2.0 * std::f64::consts::PI * 3.0 * i as f64 / n as f64).cos()).collect();
+    let mut im = vec![0.0; n];
+    fft(&mut re, &mut im);
+    for (bin, (re, im)) in re.iter().zip(&im).enumerate() {
+      let expected = if bin == 3 || bin == n - 3 { n as f64 / 2.0 } else { 0.0 };
+      assert!((re.hypot(*im) - expected).abs() < 1e-9, "bin {}", bin);
+    }
+  }
+
+  #[test]
+  fn blurring_lowers_the_high_frequency_ratio() {
+    let img = GrayImage::from_fn(128, 128, |x, y| image::Luma([((x * x + 3 * y * y + x * y) % 251) as u8]));
+    let blurred = imageproc::filter::gaussian_blur_f32(&img, 2.0);
+    assert!(high_frequency_ratio(&blurred) < high_frequency_ratio(&img) / 2.0);
+  }
+}
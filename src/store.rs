@@ -0,0 +1,285 @@
+//! A SQLite database of segment results, for querying across runs.
+//!
+//! Each completed segment is stored in one transaction, so a segment is either
+//! fully present or absent, and an interrupted run can skip the segments already stored.
+//! For example, approaches to KSFO 28L with a mean SSIM below 0.8:
+//!
+//! ```sql
+//! SELECT v.video_id, s.segment, AVG(c.ssim_score) AS mean_ssim
+//!   FROM segments s
+//!   JOIN videos v ON v.id = s.video
+//!   JOIN comparisons c ON c.segment = s.id
+//!  WHERE s.kind = 'approach' AND s.icao = 'KSFO' AND s.runway = '28L'
+//!  GROUP BY s.id
+//! HAVING mean_ssim < 0.8;
+//! ```
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use rusqlite::types::Value as SqlValue;
+use serde_json::Value;
+
+use crate::analyzer::ImageMetric;
+use crate::manifest::SegmentDescriptor;
+use crate::metrics::ComparisonMetric;
+use crate::preprocessing::Pipeline;
+use crate::records::{ColumnKind, Row, SegmentContext, COMPARISON_COLUMNS, FRAME_COLUMNS};
+
+const CREATE_TABLES: &str = "
+  CREATE TABLE IF NOT EXISTS videos (
+    id INTEGER PRIMARY KEY,
+    video_id TEXT NOT NULL UNIQUE,
+    path TEXT NOT NULL
+  );
+  CREATE TABLE IF NOT EXISTS segments (
+    id INTEGER PRIMARY KEY,
+    video INTEGER NOT NULL REFERENCES videos(id),
+    segment TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    stream TEXT NOT NULL,
+    icao TEXT NOT NULL,
+    runway TEXT NOT NULL,
+    start_frame INTEGER NOT NULL,
+    end_frame INTEGER NOT NULL,
+    annotated_frame INTEGER NOT NULL,
+    validated_runway INTEGER NOT NULL,
+    stored TEXT NOT NULL,
+    pipeline TEXT,
+    measure TEXT,
+    compare TEXT,
+    UNIQUE (video, segment)
+  );
+  CREATE TABLE IF NOT EXISTS frames (
+    segment INTEGER NOT NULL REFERENCES segments(id),
+    frame INTEGER NOT NULL,
+    nominal INTEGER,
+    PRIMARY KEY (segment, frame)
+  );
+  CREATE TABLE IF NOT EXISTS comparisons (
+    segment INTEGER NOT NULL REFERENCES segments(id),
+    frame INTEGER NOT NULL,
+    PRIMARY KEY (segment, frame)
+  );
+";
+
+fn sql_type(kind: ColumnKind) -> &'static str {
+  match kind {
+    ColumnKind::Int | ColumnKind::Bool => "INTEGER",
+    ColumnKind::Float => "REAL",
+    ColumnKind::Text => "TEXT",
+  }
+}
+
+fn sql_value(value: Option<&Value>) -> SqlValue {
+  match value {
+    Some(Value::Bool(flag)) => SqlValue::Integer(*flag as i64),
+    Some(Value::Number(num)) => match num.as_i64() {
+      Some(int) => SqlValue::Integer(int),
+      None => num.as_f64().map_or(SqlValue::Null, SqlValue::Real),
+    },
+    Some(Value::String(text)) => SqlValue::Text(text.clone()),
+    _ => SqlValue::Null,
+  }
+}
+
+fn json_value(value: SqlValue, kind: ColumnKind) -> Value {
+  match (value, kind) {
+    (SqlValue::Integer(int), ColumnKind::Bool) => Value::from(int != 0),
+    (SqlValue::Integer(int), ColumnKind::Float) => Value::from(int as f64),
+    (SqlValue::Integer(int), _) => Value::from(int),
+    (SqlValue::Real(real), _) => Value::from(real),
+    (SqlValue::Text(text), _) => Value::from(text),
+    _ => Value::Null,
+  }
+}
+
+/// Value columns of the frames table, after the segment key
+fn frame_columns() -> Vec<(&'static str, ColumnKind)> {
+  let mut columns = FRAME_COLUMNS.to_vec();
+  columns.push(("nominal", ColumnKind::Bool));
+  columns
+}
+
+/// Columns of the segments table recording how its rows were made, added to databases made without them
+const PROVENANCE_COLUMNS: [(&str, ColumnKind); 3] = [
+  ("pipeline", ColumnKind::Text),
+  ("measure", ColumnKind::Text),
+  ("compare", ColumnKind::Text),
+];
+
+/// How a segment's rows were made. Stored rows are only reused by a run that makes them the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+  /// The preprocessing pipeline, as JSON
+  pub pipeline: String,
+  /// Names of the frame metrics measured, separated by commas
+  pub measure: String,
+  /// Names of the comparison metrics, separated by commas
+  pub compare: String,
+}
+
+impl Provenance {
+  pub fn new(pipeline: &Pipeline, measure: impl IntoIterator<Item = ImageMetric>, compare: &[ComparisonMetric])
+    -> Self
+  {
+    let names = |names: BTreeSet<&str>| names.into_iter().collect::<Vec<_>>().join(",");
+    Self {
+      pipeline: serde_json::to_string(pipeline).expect("a pipeline serializes"),
+      measure: names(measure.into_iter().map(|metric| metric.name()).collect()),
+      compare: names(compare.iter().map(|metric| metric.name()).collect()),
+    }
+  }
+}
+
+pub struct ResultStore {
+  conn: Connection,
+  /// Value columns of the frames table, after the segment key
+  frame_columns: Vec<&'static str>,
+  /// Value columns of the comparisons table, after the segment key
+  comparison_columns: Vec<&'static str>,
+}
+
+impl ResultStore {
+  /// Open (or create) a database, adding any columns that records have gained since it was created
+  pub fn open(path: &Path) -> rusqlite::Result<Self> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(CREATE_TABLES)?;
+    add_missing_columns(&conn, "segments", &PROVENANCE_COLUMNS)?;
+    let frame_columns = frame_columns();
+    add_missing_columns(&conn, "frames", &frame_columns)?;
+    add_missing_columns(&conn, "comparisons", &COMPARISON_COLUMNS)?;
+
+    let mut comparison_columns = vec!["frame"];
+    comparison_columns.extend(COMPARISON_COLUMNS.iter().map(|(name, _)| *name));
+    Ok(Self {
+      conn,
+      frame_columns: frame_columns.iter().map(|(name, _)| *name).collect(),
+      comparison_columns,
+    })
+  }
+
+  /// The id of a stored segment, and how its rows were made (None if stored without a record of it)
+  fn stored_segment(&self, context: &SegmentContext) -> rusqlite::Result<Option<(i64, Option<Provenance>)>> {
+    self.conn.query_row(
+      "SELECT s.id, s.pipeline, s.measure, s.compare FROM segments s JOIN videos v ON v.id = s.video
+        WHERE v.video_id = ?1 AND s.segment = ?2",
+      params![context.video_id, context.segment],
+      |row| {
+        let provenance = match (row.get(1)?, row.get(2)?, row.get(3)?) {
+          (Some(pipeline), Some(measure), Some(compare)) => Some(Provenance { pipeline, measure, compare }),
+          _ => None,
+        };
+        Ok((row.get(0)?, provenance))
+      },
+    ).optional()
+  }
+
+  /// Whether a segment's results are already stored, however they were made
+  pub fn has_segment(&self, context: &SegmentContext) -> rusqlite::Result<bool> {
+    Ok(self.stored_segment(context)?.is_some())
+  }
+
+  /// The rows of a stored segment in frame order, or None if it isn't stored or its rows
+  /// weren't made as `provenance` says. They hold the frame and comparison columns,
+  /// and `nominal` as classified when stored.
+  pub fn load_segment(&self, context: &SegmentContext, provenance: &Provenance)
+    -> rusqlite::Result<Option<Vec<Row>>>
+  {
+    let Some((segment, Some(stored))) = self.stored_segment(context)? else {
+      return Ok(None);
+    };
+    if stored != *provenance {
+      return Ok(None);
+    }
+    let columns: Vec<(&str, ColumnKind, &str)> = frame_columns().into_iter()
+      .map(|(name, kind)| (name, kind, "f"))
+      .chain(COMPARISON_COLUMNS.iter().map(|(name, kind)| (*name, *kind, "c")))
+      .collect();
+    let selected: Vec<String> = columns.iter()
+      .map(|(name, _, table)| format!("{}.\"{}\"", table, name))
+      .collect();
+    // the first frame of a segment has no comparison
+    let mut stmt = self.conn.prepare(&format!(
+      "SELECT {} FROM frames f LEFT JOIN comparisons c ON c.segment = f.segment AND c.frame = f.frame
+        WHERE f.segment = ?1 ORDER BY f.frame",
+      selected.join(", ")))?;
+    let rows = stmt.query_map(params![segment], |sql_row| {
+      let mut row = Row::new();
+      for (i, (name, kind, _)) in columns.iter().enumerate() {
+        row.insert(name.to_string(), json_value(sql_row.get(i)?, *kind));
+      }
+      Ok(row)
+    })?;
+    rows.collect::<rusqlite::Result<Vec<Row>>>().map(Some)
+  }
+
+  /// Store the rows of one segment, made as `provenance` says, replacing any earlier results for it
+  pub fn store_segment(&mut self, seg: &SegmentDescriptor, context: &SegmentContext,
+                       provenance: &Provenance, rows: &[Row]) -> rusqlite::Result<()>
+  {
+    let previous = self.stored_segment(context)?.map(|(id, _)| id);
+    let tx = self.conn.transaction()?;
+    if let Some(previous) = previous {
+      tx.execute("DELETE FROM comparisons WHERE segment = ?1", params![previous])?;
+      tx.execute("DELETE FROM frames WHERE segment = ?1", params![previous])?;
+      tx.execute("DELETE FROM segments WHERE id = ?1", params![previous])?;
+    }
+
+    tx.execute(
+      "INSERT INTO videos (video_id, path) VALUES (?1, ?2)
+         ON CONFLICT (video_id) DO UPDATE SET path = excluded.path",
+      params![context.video_id, seg.file_path.to_string_lossy()],
+    )?;
+    let video: i64 = tx.query_row("SELECT id FROM videos WHERE video_id = ?1",
+                                  params![context.video_id], |row| row.get(0))?;
+    tx.execute(
+      "INSERT INTO segments (video, segment, kind, timestamp, stream, icao, runway,
+                             start_frame, end_frame, annotated_frame, validated_runway, stored,
+                             pipeline, measure, compare)
+       VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+      params![video, context.segment, seg.kind.as_str(), seg.timestamp_str, seg.stream,
+              seg.icao, seg.runway_designator, seg.start_frame, seg.end_frame,
+              seg.annotated_frame, seg.validated_runway, chrono::Local::now().to_rfc3339(),
+              provenance.pipeline, provenance.measure, provenance.compare],
+    )?;
+    let segment = tx.last_insert_rowid();
+
+    {
+      let mut insert_frame = tx.prepare(&insert_sql("frames", &self.frame_columns))?;
+      let mut insert_comparison = tx.prepare(&insert_sql("comparisons", &self.comparison_columns))?;
+      for row in rows {
+        let values = self.frame_columns.iter().map(|col| sql_value(row.get(*col)));
+        insert_frame.execute(params_from_iter(std::iter::once(SqlValue::Integer(segment)).chain(values)))?;
+        // the first frame of a segment has nothing to be compared with
+        let compared = COMPARISON_COLUMNS.iter().any(|(col, _)| row.get(*col).is_some_and(|val| !val.is_null()));
+        if compared {
+          let values = self.comparison_columns.iter().map(|col| sql_value(row.get(*col)));
+          insert_comparison.execute(params_from_iter(std::iter::once(SqlValue::Integer(segment)).chain(values)))?;
+        }
+      }
+    }
+    tx.commit()
+  }
+}
+
+/// `INSERT INTO <table> (segment, <columns>) VALUES (?, ...)`
+fn insert_sql(table: &str, columns: &[&str]) -> String {
+  let names: Vec<String> = columns.iter().map(|col| format!("\"{}\"", col)).collect();
+  let placeholders = vec!["?"; columns.len() + 1].join(", ");
+  format!("INSERT INTO {} (segment, {}) VALUES ({})", table, names.join(", "), placeholders)
+}
+
+fn add_missing_columns(conn: &Connection, table: &str, columns: &[(&str, ColumnKind)]) -> rusqlite::Result<()> {
+  let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+  let existing: Vec<String> = stmt.query_map([], |row| row.get::<_, String>(1))?
+    .collect::<rusqlite::Result<_>>()?;
+  for (name, kind) in columns {
+    if !existing.iter().any(|col| col == name) {
+      conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN \"{}\" {}", table, name, sql_type(*kind)))?;
+    }
+  }
+  Ok(())
+}
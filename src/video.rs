@@ -0,0 +1,576 @@
+//! Decoding a range of frames from a video file as RGB images
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use ffmpeg_next as ffmpeg;
+use ffmpeg::codec::packet::Packet;
+use ffmpeg::format::context::Input;
+use ffmpeg::format::Pixel;
+use ffmpeg::media::Type;
+use ffmpeg::software::scaling::{context::Context, flag::Flags};
+use ffmpeg::util::error::EAGAIN;
+use ffmpeg::util::frame::video::Video;
+use ffmpeg::{Rational, Rescale, Rounding};
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+/// A decoded frame: (frame_index, pts, image)
+/// - `frame_index` counts frames in presentation order from the start of the stream
+/// - `pts` is the presentation timestamp in the stream's time base
+pub type DecodedFrame = (usize, i64, RgbImage);
+
+/// Consecutive frame errors after which a stream is considered unreadable
+pub const MAX_CONSECUTIVE_ERRORS: usize = 32;
+
+/// Errors from opening or decoding a video.
+/// Frame errors (see `is_frame_error`) lose some frames, but decoding can continue past them.
+#[derive(Debug)]
+pub enum VideoError {
+  /// The file couldn't be opened, or has no decodable video stream
+  Open { path: PathBuf, source: ffmpeg::Error },
+  /// A video packet has neither pts nor dts, so the frames after it can't be numbered
+  Untimed { path: PathBuf, packet: usize },
+  /// Seeking to the start of the requested range failed
+  Seek(ffmpeg::Error),
+  /// The container couldn't be read at some point
+  Read { near_frame: usize, source: ffmpeg::Error },
+  /// The decoder rejected a packet, losing the frames that depend on it
+  CorruptPacket { near_frame: usize, source: ffmpeg::Error },
+  /// The decoder failed to produce a frame
+  Decode { near_frame: usize, source: ffmpeg::Error },
+  /// The decoder flagged a frame as corrupt
+  CorruptFrame { frame: usize },
+  /// A decoded frame couldn't be converted to RGB
+  Convert { frame: usize, source: Option<ffmpeg::Error> },
+  /// Decoding gave up after `MAX_CONSECUTIVE_ERRORS` frame errors in a row
+  TooManyErrors { near_frame: usize, last: Box<VideoError> },
+}
+
+impl VideoError {
+  /// Whether decoding can continue after this error, skipping the affected frames
+  pub fn is_frame_error(&self) -> bool {
+    matches!(self, VideoError::Read { .. } | VideoError::CorruptPacket { .. } | VideoError::Decode { .. }
+                 | VideoError::CorruptFrame { .. } | VideoError::Convert { .. })
+  }
+}
+
+impl fmt::Display for VideoError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      VideoError::Open { path, source } => write!(f, "couldn't open {:?}: {}", path, source),
+      VideoError::Untimed { path, packet } => {
+        write!(f, "video packet {} of {:?} has no timestamp, so its frames can't be numbered", packet, path)
+      }
+      VideoError::Seek(e) => write!(f, "couldn't seek to the start frame: {}", e),
+      VideoError::Read { near_frame, source } => write!(f, "couldn't read near frame {}: {}", near_frame, source),
+      VideoError::CorruptPacket { near_frame, source } => {
+        write!(f, "corrupt packet near frame {}: {}", near_frame, source)
+      }
+      VideoError::Decode { near_frame, source } => write!(f, "couldn't decode near frame {}: {}", near_frame, source),
+      VideoError::CorruptFrame { frame } => write!(f, "frame {} is corrupt", frame),
+      VideoError::Convert { frame, source: Some(e) } => write!(f, "couldn't convert frame {} to RGB: {}", frame, e),
+      VideoError::Convert { frame, source: None } => write!(f, "couldn't convert frame {} to RGB", frame),
+      VideoError::TooManyErrors { near_frame, last } => {
+        write!(f, "gave up near frame {} after {} errors in a row, the last: {}", near_frame, MAX_CONSECUTIVE_ERRORS, last)
+      }
+    }
+  }
+}
+
+impl std::error::Error for VideoError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      VideoError::Open { source, .. } | VideoError::Read { source, .. }
+        | VideoError::CorruptPacket { source, .. } | VideoError::Decode { source, .. }
+        | VideoError::Seek(source) => Some(source),
+      VideoError::Convert { source, .. } => source.as_ref().map(|e| e as &(dyn std::error::Error + 'static)),
+      VideoError::TooManyErrors { last, .. } => Some(last.as_ref()),
+      VideoError::CorruptFrame { .. } | VideoError::Untimed { .. } => None,
+    }
+  }
+}
+
+/// Position of a single frame within the video stream
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FrameEntry {
+  /// Presentation timestamp, in the stream's time base
+  pub pts: i64,
+  /// Byte offset of the frame's packet in the file, or -1 if unknown
+  pub position: i64,
+  /// Whether decoding can start at this frame
+  pub keyframe: bool,
+}
+
+/// Timing and keyframe layout of a video stream, in presentation order.
+/// Frame N is the N-th entry when all frames are sorted by pts,
+/// which holds for variable-frame-rate recordings as well.
+#[derive(Debug, Clone)]
+pub struct FrameIndex {
+  /// Time base of the pts values
+  pub time_base: Rational,
+  /// Average frame rate reported by the container
+  pub frame_rate: Rational,
+  /// Width of decoded frames
+  pub width: u32,
+  /// Height of decoded frames
+  pub height: u32,
+  /// All frames of the stream, sorted by pts
+  pub frames: Vec<FrameEntry>,
+}
+
+/// On-disk form of a `FrameIndex`, stored next to the video it describes
+#[derive(Serialize, Deserialize)]
+struct IndexSidecar {
+  /// Size of the video file when the index was built
+  source_len: u64,
+  /// Modification time of the video file (seconds since epoch) when the index was built
+  source_modified: u64,
+  time_base: (i32, i32),
+  frame_rate: (i32, i32),
+  width: u32,
+  height: u32,
+  frames: Vec<FrameEntry>,
+}
+
+/// Path of the index sidecar for a video, eg `foo.mp4` -> `foo.mp4.vidx.json`
+pub fn sidecar_path(video_path: &Path) -> PathBuf {
+  let mut name = video_path.as_os_str().to_owned();
+  name.push(".vidx.json");
+  PathBuf::from(name)
+}
+
+/// (length, modification time) used to detect a video that changed since it was indexed
+fn source_stamp(video_path: &Path) -> std::io::Result<(u64, u64)> {
+  let meta = std::fs::metadata(video_path)?;
+  let modified = meta.modified()?
+    .duration_since(UNIX_EPOCH)
+    .map(|dur| dur.as_secs())
+    .unwrap_or(0);
+  Ok((meta.len(), modified))
+}
+
+impl FrameIndex {
+  /// Build the index by demuxing (but not decoding) every packet of the best video stream.
+  /// Fails if a packet has no timestamp, as frames couldn't then be put in presentation order.
+  pub fn scan<P: AsRef<Path>>(path: P) -> Result<Self, VideoError> {
+    let open_error = |source| VideoError::Open { path: path.as_ref().to_path_buf(), source };
+    ffmpeg::init().map_err(open_error)?;
+    let mut ictx = ffmpeg::format::input(&path).map_err(open_error)?;
+    let (stream_index, time_base, frame_rate, width, height) = {
+      let input = ictx
+        .streams()
+        .best(Type::Video)
+        .ok_or_else(|| open_error(ffmpeg::Error::StreamNotFound))?;
+      let context_decoder =
+        ffmpeg::codec::context::Context::from_parameters(input.parameters()).map_err(open_error)?;
+      let decoder = context_decoder.decoder().video().map_err(open_error)?;
+      (input.index(), input.time_base(), input.avg_frame_rate(), decoder.width(), decoder.height())
+    };
+
+    let mut frames: Vec<FrameEntry> = Vec::new();
+    for (stream, packet) in ictx.packets() {
+      if stream.index() == stream_index {
+        let Some(pts) = packet.pts().or(packet.dts()) else {
+          return Err(VideoError::Untimed { path: path.as_ref().to_path_buf(), packet: frames.len() });
+        };
+        frames.push(FrameEntry {
+          pts,
+          position: packet.position() as i64,
+          keyframe: packet.is_key(),
+        });
+      }
+    }
+    // packets arrive in decode order
+    frames.sort_by_key(|entry| entry.pts);
+
+    Ok(Self { time_base, frame_rate, width, height, frames })
+  }
+
+  /// Load the sidecar index for a video, if one exists and is still current
+  pub fn load(video_path: &Path) -> std::io::Result<Self> {
+    let file = File::open(sidecar_path(video_path))?;
+    let sidecar: IndexSidecar = serde_json::from_reader(BufReader::new(file))?;
+    let (source_len, source_modified) = source_stamp(video_path)?;
+    if sidecar.source_len != source_len || sidecar.source_modified != source_modified {
+      return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "stale video index"));
+    }
+    Ok(Self {
+      time_base: Rational::new(sidecar.time_base.0, sidecar.time_base.1),
+      frame_rate: Rational::new(sidecar.frame_rate.0, sidecar.frame_rate.1),
+      width: sidecar.width,
+      height: sidecar.height,
+      frames: sidecar.frames,
+    })
+  }
+
+  /// Write this index as the sidecar for the given video
+  pub fn save(&self, video_path: &Path) -> std::io::Result<()> {
+    let (source_len, source_modified) = source_stamp(video_path)?;
+    let sidecar = IndexSidecar {
+      source_len,
+      source_modified,
+      time_base: (self.time_base.numerator(), self.time_base.denominator()),
+      frame_rate: (self.frame_rate.numerator(), self.frame_rate.denominator()),
+      width: self.width,
+      height: self.height,
+      frames: self.frames.clone(),
+    };
+    let file = File::create(sidecar_path(video_path))?;
+    serde_json::to_writer(BufWriter::new(file), &sidecar)?;
+    Ok(())
+  }
+
+  /// Load the sidecar index for a video, or scan the video and cache a new sidecar
+  pub fn load_or_build(video_path: &Path) -> Result<Self, VideoError> {
+    if let Ok(index) = Self::load(video_path) {
+      return Ok(index);
+    }
+    let index = Self::scan(video_path)?;
+    if let Err(e) = index.save(video_path) {
+      // the index is still usable, it just won't be reused next time
+      eprintln!("Unable to save index for {:?}: {}", video_path, e);
+    }
+    Ok(index)
+  }
+
+  /// Total number of frames in the stream
+  pub fn frame_count(&self) -> usize {
+    self.frames.len()
+  }
+
+  /// Presentation timestamp of a frame, in the stream's time base
+  pub fn pts(&self, frame: usize) -> Option<i64> {
+    self.frames.get(frame).map(|entry| entry.pts)
+  }
+
+  /// Presentation time of a frame in seconds
+  pub fn seconds(&self, frame: usize) -> Option<f64> {
+    self.pts(frame).map(|pts| pts as f64 * f64::from(self.time_base))
+  }
+
+  /// Find the frame with exactly the given presentation timestamp
+  pub fn frame_for_pts(&self, pts: i64) -> Option<usize> {
+    self.frames.binary_search_by_key(&pts, |entry| entry.pts).ok()
+  }
+
+  /// Find the closest keyframe at or before the given frame
+  pub fn keyframe_before(&self, frame: usize) -> Option<usize> {
+    let last = frame.min(self.frames.len().checked_sub(1)?);
+    (0..=last).rev().find(|&idx| self.frames[idx].keyframe)
+  }
+}
+
+/// Decodes the inclusive frame range `[start_frame, end_frame]` of the best
+/// video stream in a file, yielding each frame as an `RgbImage`.
+pub struct FrameSource {
+  ictx: Input,
+  decoder: ffmpeg::decoder::Video,
+  scaler: Context,
+  stream_index: usize,
+  index: FrameIndex,
+  start_frame: usize,
+  end_frame: usize,
+  /// Index assumed for the next decoded frame if it has no usable timestamp
+  next_index: usize,
+  /// Frame errors since the last good frame
+  consecutive_errors: usize,
+  eof_sent: bool,
+  /// Set once we've fallen back to decoding from the start of the stream
+  rewound: bool,
+  /// Set once a frame at or past `start_frame` has been decoded, or a frame error reported there:
+  /// any gap before the next frame is then lost frames, not a seek that overshot
+  reached_start: bool,
+  done: bool,
+  decoded: Video,
+}
+
+impl FrameSource {
+  /// Open a video file for decoding the inclusive range `[start_frame, end_frame]`,
+  /// scanning the file to locate frames
+  pub fn open<P: AsRef<Path>>(path: P, start_frame: usize, end_frame: usize)
+    -> Result<Self, VideoError>
+  {
+    let index = FrameIndex::scan(&path)?;
+    Self::open_with_index(path, index, start_frame, end_frame)
+  }
+
+  /// Open a video file for decoding the inclusive range `[start_frame, end_frame]`,
+  /// reusing (or creating) the index sidecar next to the video
+  pub fn open_indexed<P: AsRef<Path>>(path: P, start_frame: usize, end_frame: usize)
+    -> Result<Self, VideoError>
+  {
+    let index = FrameIndex::load_or_build(path.as_ref())?;
+    Self::open_with_index(path, index, start_frame, end_frame)
+  }
+
+  /// Open a video file for decoding the inclusive range `[start_frame, end_frame]`,
+  /// using a previously built index to seek directly to the nearest keyframe
+  pub fn open_with_index<P: AsRef<Path>>(path: P, index: FrameIndex,
+                                         start_frame: usize, end_frame: usize)
+    -> Result<Self, VideoError>
+  {
+    let open_error = |source| VideoError::Open { path: path.as_ref().to_path_buf(), source };
+    ffmpeg::init().map_err(open_error)?;
+    let ictx = ffmpeg::format::input(&path).map_err(open_error)?;
+    let input = ictx
+      .streams()
+      .best(Type::Video)
+      .ok_or_else(|| open_error(ffmpeg::Error::StreamNotFound))?;
+    let stream_index = input.index();
+
+    let context_decoder =
+      ffmpeg::codec::context::Context::from_parameters(input.parameters()).map_err(open_error)?;
+    let decoder = context_decoder.decoder().video().map_err(open_error)?;
+
+    let scaler = Context::get(
+      decoder.format(),
+      decoder.width(),
+      decoder.height(),
+      Pixel::RGB24,
+      decoder.width(),
+      decoder.height(),
+      Flags::BILINEAR,
+    ).map_err(open_error)?;
+
+    let mut source = Self {
+      ictx,
+      decoder,
+      scaler,
+      stream_index,
+      index,
+      start_frame,
+      end_frame,
+      next_index: 0,
+      consecutive_errors: 0,
+      eof_sent: false,
+      rewound: false,
+      reached_start: false,
+      done: false,
+      decoded: Video::empty(),
+    };
+    source.seek_to_start().map_err(VideoError::Seek)?;
+    Ok(source)
+  }
+
+  /// Width of decoded frames
+  pub fn width(&self) -> u32 {
+    self.decoder.width()
+  }
+
+  /// Height of decoded frames
+  pub fn height(&self) -> u32 {
+    self.decoder.height()
+  }
+
+  /// The frame index used to locate frames in this video
+  pub fn index(&self) -> &FrameIndex {
+    &self.index
+  }
+
+  /// Jump to the keyframe preceding `start_frame`
+  fn seek_to_start(&mut self) -> Result<(), ffmpeg::Error> {
+    let keyframe = match self.index.keyframe_before(self.start_frame) {
+      Some(keyframe) if keyframe > 0 => keyframe,
+      // the decoder is already positioned at the start of the stream
+      _ => return Ok(()),
+    };
+    let key_pts = self.index.frames[keyframe].pts;
+    // seeking across all streams takes a timestamp in AV_TIME_BASE units
+    let ts = key_pts.rescale_with(self.index.time_base, ffmpeg::rescale::TIME_BASE, Rounding::Down);
+    self.ictx.seek(ts, ..ts)?;
+    self.decoder.flush();
+    self.next_index = keyframe;
+    Ok(())
+  }
+
+  /// Fall back to decoding from the very start of the stream,
+  /// for containers where the keyframe seek overshot the start frame
+  fn rewind(&mut self) -> Result<(), ffmpeg::Error> {
+    self.rewound = true;
+    self.ictx.seek(0, ..)?;
+    self.decoder.flush();
+    self.next_index = 0;
+    self.eof_sent = false;
+    Ok(())
+  }
+
+  /// Send the next video packet (or end-of-stream) to the decoder
+  fn feed_decoder(&mut self) -> Result<(), VideoError> {
+    let mut packet = Packet::empty();
+    loop {
+      match packet.read(&mut self.ictx) {
+        Ok(()) => {
+          if packet.stream() == self.stream_index {
+            match self.decoder.send_packet(&packet) {
+              Ok(()) => return Ok(()),
+              Err(source) => self.stream_error(VideoError::CorruptPacket { near_frame: self.next_index, source })?,
+            }
+          }
+        }
+        Err(ffmpeg::Error::Eof) => {
+          self.eof_sent = true;
+          return match self.decoder.send_eof() {
+            Ok(()) => Ok(()),
+            Err(source) => Err(self.fail(VideoError::Read { near_frame: self.next_index, source })),
+          };
+        }
+        Err(source) => self.stream_error(VideoError::Read { near_frame: self.next_index, source })?,
+      }
+    }
+  }
+
+  /// Count a frame error that came before the frame it loses was decoded. In the lead-in before
+  /// `start_frame` it only loses frames that are skipped anyway, so decoding carries on without
+  /// reporting it (unless it's given up on).
+  fn stream_error(&mut self, error: VideoError) -> Result<(), VideoError> {
+    let lead_in = self.next_index < self.start_frame;
+    let error = self.frame_error(error);
+    if lead_in && !self.done {
+      return Ok(());
+    }
+    self.reached_start = true;
+    Err(error)
+  }
+
+  /// Count an error that only loses frames, giving up on the stream once too many come in a row
+  fn frame_error(&mut self, error: VideoError) -> VideoError {
+    self.consecutive_errors += 1;
+    if self.consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+      self.fail(VideoError::TooManyErrors { near_frame: self.next_index, last: Box::new(error) })
+    } else {
+      error
+    }
+  }
+
+  /// End decoding with an error
+  fn fail(&mut self, error: VideoError) -> VideoError {
+    self.done = true;
+    error
+  }
+}
+
+/// Yields the frames of the range in order. After a frame error (`VideoError::is_frame_error`)
+/// iteration can continue with the next readable frame; any other error ends the iteration.
+impl Iterator for FrameSource {
+  type Item = Result<DecodedFrame, VideoError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while !self.done {
+      match self.decoder.receive_frame(&mut self.decoded) {
+        Ok(()) => {
+          let pts = self.decoded.timestamp().or(self.decoded.pts());
+          let index = pts
+            .and_then(|pts| self.index.frame_for_pts(pts))
+            .unwrap_or(self.next_index);
+          self.next_index = index + 1;
+
+          if index < self.start_frame {
+            // decoded only to advance the decoder to the region of interest
+            self.consecutive_errors = 0;
+            continue;
+          }
+          if !self.reached_start && index > self.start_frame && !self.rewound {
+            if let Err(e) = self.rewind() {
+              return Some(Err(self.fail(VideoError::Seek(e))));
+            }
+            continue;
+          }
+          self.reached_start = true;
+          if index > self.end_frame {
+            self.done = true;
+            break;
+          }
+
+          if self.decoded.is_corrupt() {
+            return Some(Err(self.frame_error(VideoError::CorruptFrame { frame: index })));
+          }
+          let mut rgb_frame = Video::empty();
+          if let Err(e) = self.scaler.run(&self.decoded, &mut rgb_frame) {
+            return Some(Err(self.frame_error(VideoError::Convert { frame: index, source: Some(e) })));
+          }
+          let Some(rgb_img) = rgb_frame_to_image(&rgb_frame) else {
+            return Some(Err(self.frame_error(VideoError::Convert { frame: index, source: None })));
+          };
+          self.consecutive_errors = 0;
+          let pts = pts.or(self.index.pts(index)).unwrap_or(0);
+          return Some(Ok((index, pts, rgb_img)));
+        }
+        Err(ffmpeg::Error::Eof) => self.done = true,
+        // the decoder needs more input
+        Err(ffmpeg::Error::Other { errno: EAGAIN }) if self.eof_sent => self.done = true,
+        Err(ffmpeg::Error::Other { errno: EAGAIN }) => {
+          if let Err(e) = self.feed_decoder() {
+            return Some(Err(e));
+          }
+        }
+        Err(source) => {
+          if let Err(e) = self.stream_error(VideoError::Decode { near_frame: self.next_index, source }) {
+            return Some(Err(e));
+          }
+        }
+      }
+    }
+    None
+  }
+}
+
+/// Copy an RGB24 video frame into an `RgbImage`, dropping any row padding.
+/// None if the frame holds less data than its dimensions call for.
+pub fn rgb_frame_to_image(frame: &Video) -> Option<RgbImage> {
+  let (width, height) = (frame.width(), frame.height());
+  let row_len = width as usize * 3;
+  let stride = frame.stride(0);
+  let mut buf: Vec<u8> = Vec::with_capacity(row_len * height as usize);
+  for row in frame.data(0).chunks(stride).take(height as usize) {
+    buf.extend_from_slice(row.get(..row_len)?);
+  }
+  RgbImage::from_raw(width, height, buf)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A variable-frame-rate index with keyframes at the given frames
+  fn index(pts: &[i64], keyframes: &[usize]) -> FrameIndex {
+    FrameIndex {
+      time_base: Rational::new(1, 1000),
+      frame_rate: Rational::new(30, 1),
+      width: 1920,
+      height: 1080,
+      frames: pts.iter().enumerate()
+        .map(|(frame, pts)| FrameEntry { pts: *pts, position: -1, keyframe: keyframes.contains(&frame) })
+        .collect(),
+    }
+  }
+
+  #[test]
+  fn frames_are_found_by_exact_pts() {
+    let index = index(&[0, 33, 67, 120, 133, 200], &[0, 3]);
+    assert_eq!(index.frame_for_pts(0), Some(0));
+    assert_eq!(index.frame_for_pts(120), Some(3));
+    assert_eq!(index.frame_for_pts(200), Some(5));
+    assert_eq!(index.frame_for_pts(100), None);
+    assert_eq!(index.frame_for_pts(-33), None);
+    assert_eq!(index.frame_for_pts(233), None);
+    assert_eq!(index.seconds(3), Some(0.12));
+  }
+
+  #[test]
+  fn keyframe_before_looks_back_to_the_nearest_keyframe() {
+    assert_eq!(index(&[0, 33, 67], &[2]).keyframe_before(1), None);
+    assert_eq!(index(&[], &[]).keyframe_before(0), None);
+
+    let index = index(&[0, 33, 67, 120, 133, 200], &[0, 3]);
+    assert_eq!(index.keyframe_before(0), Some(0));
+    assert_eq!(index.keyframe_before(2), Some(0));
+    assert_eq!(index.keyframe_before(3), Some(3));
+    assert_eq!(index.keyframe_before(5), Some(3));
+    // past the end, the last keyframe
+    assert_eq!(index.keyframe_before(100), Some(3));
+  }
+}
@@ -0,0 +1,197 @@
+//! Modeling lens vignetting, and flattening it out of frames so they needn't be cropped
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use image::{GrayImage, ImageBuffer, Pixel};
+use serde::{Deserialize, Serialize};
+
+/// Radial bins the mean intensity is gathered into when fitting
+const RADIAL_BINS: usize = 64;
+
+/// Gains are kept at least this large, so the darkest corners aren't amplified without limit
+const MIN_GAIN: f64 = 0.05;
+
+/// Relative brightness of the lens across the frame, as an even polynomial in the distance
+/// from the frame center: `gain(r) = 1 + a r^2 + b r^4 + c r^6`, with `r` 0 at the center
+/// and 1 at the corners. Fit to full, uncropped frames.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct VignettingModel {
+  /// Coefficients of r^2, r^4 and r^6
+  pub coefficients: [f64; 3],
+}
+
+impl Default for VignettingModel {
+  /// No vignetting
+  fn default() -> Self {
+    Self { coefficients: [0.0; 3] }
+  }
+}
+
+impl VignettingModel {
+  /// Fit the falloff of the mean intensity with radius over a batch of frames.
+  /// Scene content averages out over many frames of varied scenes, leaving the lens.
+  /// Returns None without frames, or if they're too dark to fit.
+  pub fn fit<'a, I>(frames: I) -> Option<Self>
+    where I: IntoIterator<Item = &'a GrayImage>
+  {
+    let mut sums = [0.0; RADIAL_BINS];
+    let mut counts = [0u64; RADIAL_BINS];
+    for frame in frames {
+      let radius = Radius::new(frame.width(), frame.height());
+      for (x, y, pixel) in frame.enumerate_pixels() {
+        let bin = ((radius.squared(x, y).sqrt() * RADIAL_BINS as f64) as usize).min(RADIAL_BINS - 1);
+        sums[bin] += pixel.0[0] as f64;
+        counts[bin] += 1;
+      }
+    }
+
+    // weighted least squares for intensity = k (1 + a r^2 + b r^4 + c r^6),
+    // which is linear in (k, k a, k b, k c)
+    let mut normal = [[0.0; 4]; 4];
+    let mut rhs = [0.0; 4];
+    for bin in (0..RADIAL_BINS).filter(|bin| counts[*bin] > 0) {
+      let r2 = ((bin as f64 + 0.5) / RADIAL_BINS as f64).powi(2);
+      let terms = [1.0, r2, r2 * r2, r2 * r2 * r2];
+      let weight = counts[bin] as f64;
+      let mean = sums[bin] / weight;
+      for row in 0..4 {
+        rhs[row] += weight * terms[row] * mean;
+        for col in 0..4 {
+          normal[row][col] += weight * terms[row] * terms[col];
+        }
+      }
+    }
+    let [k, ka, kb, kc] = solve(normal, rhs)?;
+    if k <= 0.0 || !k.is_finite() {
+      return None;
+    }
+    Some(Self { coefficients: [ka / k, kb / k, kc / k] })
+  }
+
+  /// Relative brightness at `r2`, the squared normalized radius
+  fn gain_at(&self, r2: f64) -> f64 {
+    let [a, b, c] = self.coefficients;
+    (1.0 + r2 * (a + r2 * (b + r2 * c))).max(MIN_GAIN)
+  }
+
+  /// Relative brightness at normalized radius `r`: 1 at the center
+  pub fn gain(&self, r: f64) -> f64 {
+    self.gain_at(r * r)
+  }
+
+  /// Divide out the vignetting, brightening the edges to match the center.
+  /// Works on gray or color frames, which must be uncropped.
+  pub fn devignette<P>(&self, img: &ImageBuffer<P, Vec<u8>>) -> ImageBuffer<P, Vec<u8>>
+    where P: Pixel<Subpixel = u8>
+  {
+    let radius = Radius::new(img.width(), img.height());
+    let mut out = img.clone();
+    for (x, y, pixel) in out.enumerate_pixels_mut() {
+      let scale = 1.0 / self.gain_at(radius.squared(x, y));
+      for channel in pixel.channels_mut() {
+        *channel = (*channel as f64 * scale).round().min(u8::MAX as f64) as u8;
+      }
+    }
+    out
+  }
+
+  pub fn load(path: &Path) -> std::io::Result<Self> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+  }
+
+  pub fn save(&self, path: &Path) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+    Ok(())
+  }
+}
+
+/// Normalized distance of pixels from the center of a frame
+struct Radius {
+  center_x: f64,
+  center_y: f64,
+  /// Squared distance from the center to a corner
+  corner2: f64,
+}
+
+impl Radius {
+  fn new(width: u32, height: u32) -> Self {
+    let center_x = (width as f64 - 1.0) / 2.0;
+    let center_y = (height as f64 - 1.0) / 2.0;
+    Self { center_x, center_y, corner2: (center_x * center_x + center_y * center_y).max(1.0) }
+  }
+
+  /// Squared distance of a pixel from the center, 1 at the corners
+  fn squared(&self, x: u32, y: u32) -> f64 {
+    let dx = x as f64 - self.center_x;
+    let dy = y as f64 - self.center_y;
+    (dx * dx + dy * dy) / self.corner2
+  }
+}
+
+/// Solve a small linear system by Gaussian elimination with partial pivoting.
+/// Returns None if it's singular.
+fn solve<const N: usize>(mut matrix: [[f64; N]; N], mut rhs: [f64; N]) -> Option<[f64; N]> {
+  for col in 0..N {
+    let pivot = (col..N).max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))?;
+    if matrix[pivot][col].abs() < 1e-12 {
+      return None;
+    }
+    matrix.swap(col, pivot);
+    rhs.swap(col, pivot);
+    let (upper, lower) = matrix.split_at_mut(col + 1);
+    let pivot_row = &upper[col];
+    for (offset, row) in lower.iter_mut().enumerate() {
+      let factor = row[col] / pivot_row[col];
+      for (val, pivot_val) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+        *val -= factor * pivot_val;
+      }
+      rhs[col + 1 + offset] -= factor * rhs[col];
+    }
+  }
+  let mut solution = [0.0; N];
+  for row in (0..N).rev() {
+    let sum: f64 = (row + 1..N).map(|k| matrix[row][k] * solution[k]).sum();
+    solution[row] = (rhs[row] - sum) / matrix[row][row];
+  }
+  Some(solution)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn solve_recovers_a_known_system() {
+    // needs pivoting: the first column starts with a zero
+    let matrix = [[0.0, 2.0, 1.0], [1.0, 1.0, 1.0], [4.0, -1.0, 3.0]];
+    let expected = [1.5, -2.0, 0.5];
+    let rhs: [f64; 3] = std::array::from_fn(|row| (0..3).map(|col| matrix[row][col] * expected[col]).sum());
+    let solution = solve(matrix, rhs).unwrap();
+    for (found, expected) in solution.iter().zip(expected) {
+      assert!((found - expected).abs() < 1e-12);
+    }
+  }
+
+  #[test]
+  fn solve_rejects_a_singular_system() {
+    assert!(solve([[1.0, 2.0], [2.0, 4.0]], [1.0, 2.0]).is_none());
+  }
+
+  #[test]
+  fn fit_recovers_a_synthetic_falloff() {
+    let lens = VignettingModel { coefficients: [-0.3, 0.05, -0.02] };
+    let radius = Radius::new(160, 120);
+    let frame = GrayImage::from_fn(160, 120, |x, y| {
+      image::Luma([(200.0 * lens.gain_at(radius.squared(x, y))).round() as u8])
+    });
+    let fitted = VignettingModel::fit([&frame]).unwrap();
+    for r in [0.25, 0.5, 0.75, 1.0] {
+      assert!((fitted.gain(r) - lens.gain(r)).abs() < 0.01, "gain at {}", r);
+    }
+  }
+}